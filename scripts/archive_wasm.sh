@@ -0,0 +1,3 @@
+cargo build --release --target wasm32-unknown-unknown --package icrc7_archive
+ic-wasm target/wasm32-unknown-unknown/release/icrc7_archive.wasm -o src/icrc7_archive/wasm/icrc7_archive.wasm shrink
+gzip -f -c src/icrc7_archive/wasm/icrc7_archive.wasm > src/icrc7_archive/wasm/icrc7_archive.wasm.gz
@@ -1,4 +1,4 @@
-dfx deploy icrc7 --argument '(record{                                  
+dfx deploy icrc7 --argument '(variant { Init = record{                                  
 minting_account= opt record {
         owner = principal "3yyxm-t5fpe-v32em-ac6lr-xyort-wuscb-dvl4x-3wnwi-hqkyj-xortw-oqe";                                     
         subaccount = opt blob "\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00";
@@ -27,7 +27,7 @@ archive_init= opt record {
         maxRecordsInArchiveInstance= 4;
         archiveControllers= null
     }
-})'
+}})'
 
 dfx canister call icrc7 icrc7_mint '(record{                                  
 to= record {
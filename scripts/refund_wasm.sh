@@ -0,0 +1,3 @@
+cargo build --release --target wasm32-unknown-unknown --package icrc7_refund
+ic-wasm target/wasm32-unknown-unknown/release/icrc7_refund.wasm -o target/wasm32-unknown-unknown/release/icrc7_refund.wasm shrink
+gzip -f -c target/wasm32-unknown-unknown/release/icrc7_refund.wasm > wasm/icrc7_refund.wasm.gz
@@ -0,0 +1,4 @@
+# Regenerates the types the ledger shares with other canisters from the ledger sources.
+cp src/icrc7/src/icrc7_types.rs src/icrc7_types/src/icrc7_types.rs
+sed 's/^use crate::Transaction;/use crate::icrc7_types::Transaction;/' src/icrc7/src/icrc3_types.rs > src/icrc7_types/src/icrc3_types.rs
+cp src/icrc7/src/errors.rs src/icrc7_types/src/errors.rs
@@ -3,9 +3,10 @@ use ic_cdk::api::management_canister::{
     main::{create_canister, install_code, CreateCanisterArgument, InstallCodeArgument},
     provisional::CanisterSettings,
 };
+use icrc_ledger_types::icrc::generic_value::Hash;
 use serde::Serialize;
 
-use crate::icrc3_types::{ArchiveCreateArgs, IndexType};
+use crate::icrc3_types::{ArchiveCreateArgs, BlockType, IndexType};
 
 pub const ARCHIVE_WASM: &[u8] =
     std::include_bytes!("./../../icrc7_archive/wasm/icrc7_archive.wasm.gz");
@@ -18,15 +19,19 @@ pub struct ArchiveInitArgs {
     pub index_type: IndexType,
     pub max_pages: u128,
     pub max_records: u128,
+    pub supported_blocks: Option<Vec<BlockType>>,
+    pub parent_hash: Option<Hash>,
 }
 
 impl ArchiveInitArgs {
-    fn new(max_pages: u128, max_records: u128, first_index: u128) -> Self {
+    fn new(arg: ArchiveCreateArgs) -> Self {
         Self {
-            index_type: IndexType::Stable,
-            first_index,
-            max_pages,
-            max_records,
+            index_type: arg.index_type,
+            first_index: arg.first_index,
+            max_pages: arg.max_pages,
+            max_records: arg.max_records,
+            supported_blocks: Some(arg.supported_blocks),
+            parent_hash: arg.parent_hash,
         }
     }
 }
@@ -35,7 +40,7 @@ impl ArchiveInitArgs {
 pub async fn create_archive_canister(arg: ArchiveCreateArgs) -> Result<Principal, String> {
     let mut archive_controllers = vec![ic_cdk::id()];
 
-    if let Some(Some(controllers)) = arg.controllers {
+    if let Some(Some(controllers)) = arg.controllers.clone() {
         if !controllers.is_empty() {
             archive_controllers.extend(controllers);
         }
@@ -60,7 +65,7 @@ pub async fn create_archive_canister(arg: ArchiveCreateArgs) -> Result<Principal
     };
     ic_cdk::println!("new archive canister: {}", principal);
 
-    let init_arg = ArchiveInitArgs::new(arg.max_pages, arg.max_records, arg.first_index);
+    let init_arg = ArchiveInitArgs::new(arg);
     let init_arg = Encode!(&init_arg).unwrap();
     match install_code(InstallCodeArgument {
         mode: ic_cdk::api::management_canister::main::CanisterInstallMode::Install,
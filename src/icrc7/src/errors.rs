@@ -21,7 +21,7 @@ pub enum BurnError {
     GenericBatchError { error_code: u128, message: String },
 }
 
-#[derive(CandidType, Clone)]
+#[derive(CandidType, Deserialize, Clone, Debug)]
 pub enum MintError {
     SupplyCapReached,
     Unauthorized,
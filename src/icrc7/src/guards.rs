@@ -15,6 +15,16 @@ pub fn owner_guard() -> Result<(), String> {
     }
 }
 
+// The minting authority, or a controller of the ledger, which could reinstall it anyway.
+#[inline(always)]
+pub fn owner_or_controller_guard() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&caller()) {
+        Ok(())
+    } else {
+        owner_guard()
+    }
+}
+
 #[inline(always)]
 pub fn authenticated_guard() -> Result<(), String> {
     if ic_cdk::caller() == Principal::anonymous() {
@@ -66,6 +66,29 @@ impl Default for LedgerInfo {
     }
 }
 
+impl LedgerInfo {
+    // Replaces the limits set in `arg`, the others keep their value.
+    pub fn apply(&mut self, arg: InitApprovalsArg) {
+        if let Some(max_approvals_per_token_or_collection) =
+            arg.max_approvals_per_token_or_collection
+        {
+            self.max_approvals_per_token_or_collection = max_approvals_per_token_or_collection;
+        }
+        if let Some(max_revoke_approvals) = arg.max_revoke_approvals {
+            self.max_revoke_approvals = max_revoke_approvals;
+        }
+        if let Some(max_approvals) = arg.max_approvals {
+            self.max_approvals = max_approvals;
+        }
+        if let Some(settle_to_approvals) = arg.settle_to_approvals {
+            self.settle_to_approvals = settle_to_approvals;
+        }
+        if let Some(collection_approval_requires_token) = arg.collection_approval_requires_token {
+            self.collection_approval_requires_token = collection_approval_requires_token;
+        }
+    }
+}
+
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
 pub struct ApprovalInfo {
     pub from_subaccount: Option<Subaccount>,
@@ -219,7 +242,7 @@ impl Storable for CollectionApprovalAccount {
     const BOUND: Bound = Bound::Unbounded;
 }
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct InitApprovalsArg {
     pub max_approvals: Option<u16>,
     pub max_approvals_per_token_or_collection: Option<u16>,
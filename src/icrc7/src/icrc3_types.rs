@@ -68,37 +68,67 @@ impl TryFrom<Value> for Block {
     }
 }
 
+// Returns the fields of the `tx` map defined by the ICRC-7 and ICRC-37 block schemas.
+// `memo` and `ts` (the caller's `created_at_time`) are optional for every block type.
+pub fn tx_schema(btype: &str) -> &'static [&'static str] {
+    match btype {
+        "7mint" => &["tid", "from", "to", "meta"],
+        "7burn" => &["tid", "from"],
+        "7xfer" => &["tid", "from", "to"],
+        "7update" => &["tid", "from", "meta"],
+        "37appr" => &["tid", "from", "spender", "exp"],
+        "37appr_coll" => &["from", "spender", "exp"],
+        "37revoke" => &["tid", "from", "spender"],
+        "37revoke_coll" => &["from", "spender"],
+        "37xfer" => &["tid", "spender", "from", "to"],
+        "7admin_burn" => &["tid", "from", "authority"],
+        "7admin_xfer" => &["tid", "from", "to", "authority"],
+        _ => &["tid", "from", "to", "spender", "exp", "meta", "authority"],
+    }
+}
+
 impl Block {
     pub fn new(phash: Option<Hash>, tx: Transaction) -> Self {
         let mut block = Map::new();
+        // the first block of the chain has no parent
         if let Some(phash) = phash {
             block.insert("phash".to_string(), Value::Blob(ByteBuf::from(phash)));
         };
 
-        block.insert("btype".to_string(), Value::Text(tx.op));
+        block.insert("btype".to_string(), Value::Text(tx.op.clone()));
         block.insert("ts".to_string(), Value::Nat(tx.ts.into()));
 
+        let schema = tx_schema(&tx.op);
+        let has = |field: &str| schema.contains(&field);
+
         let mut val = Map::new();
-        val.insert("tid".to_string(), Value::Nat(tx.tid.into()));
-        if let Some(from) = tx.from {
+        if has("tid") {
+            val.insert("tid".to_string(), Value::Nat(tx.tid.into()));
+        }
+        if let Some(from) = tx.from.filter(|_| has("from")) {
             val.insert("from".to_string(), account_value(from));
         }
-        if let Some(to) = tx.to {
+        if let Some(to) = tx.to.filter(|_| has("to")) {
             val.insert("to".to_string(), account_value(to));
         }
-        if let Some(spender) = tx.spender {
+        if let Some(spender) = tx.spender.filter(|_| has("spender")) {
             val.insert("spender".to_string(), account_value(spender));
         }
-        if let Some(exp) = tx.exp {
+        if let Some(exp) = tx.exp.filter(|_| has("exp")) {
             val.insert("exp".to_string(), Value::Nat(exp.into()));
         }
-        if let Some(meta) = tx.meta {
+        if let Some(meta) = tx.meta.filter(|_| has("meta")) {
             val.insert("meta".to_string(), Value::Map(meta));
         }
+        if let Some(authority) = tx.authority.filter(|_| has("authority")) {
+            val.insert("authority".to_string(), account_value(authority));
+        }
         if let Some(memo) = tx.memo {
             val.insert("memo".to_string(), Value::Blob(ByteBuf::from(memo)));
         }
-        val.insert("ts".to_string(), Value::Nat(tx.ts.into()));
+        if let Some(created_at_time) = tx.created_at_time {
+            val.insert("ts".to_string(), Value::Nat(created_at_time.into()));
+        }
         block.insert("tx".to_string(), Value::Map(val));
         Self(Value::Map(block))
     }
@@ -142,28 +172,42 @@ pub struct TransactionRange {
     pub length: u128,
 }
 
+impl Storable for TransactionRange {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
 pub struct ArchiveLedgerInfo {
-    pub archives: BTreeMap<Principal, TransactionRange>,
     pub local_ledger_size: u128,
     pub supported_blocks: Vec<BlockType>,
     pub last_index: u128,
     pub first_index: u128,
     pub is_cleaning: bool,
     pub latest_hash: Option<Hash>,
+    // hash of the last block moved to an archive, the parent of the first local block
+    #[serde(default)]
+    pub archived_hash: Option<Hash>,
     pub setting: ArchiveSetting,
 }
 
 impl Default for ArchiveLedgerInfo {
     fn default() -> Self {
         Self {
-            archives: BTreeMap::new(),
             local_ledger_size: 0,
             supported_blocks: vec![],
             last_index: 0,
             first_index: 0,
             is_cleaning: false,
             latest_hash: None,
+            archived_hash: None,
             setting: ArchiveSetting::default(),
         }
     }
@@ -173,12 +217,12 @@ impl ArchiveLedgerInfo {
     pub fn new(setting: Option<ArchiveSetting>) -> Self {
         let setting = setting.unwrap_or(ArchiveSetting::default());
         Self {
-            archives: BTreeMap::new(),
             local_ledger_size: 0,
             last_index: 0,
             first_index: 0,
             is_cleaning: false,
             latest_hash: None,
+            archived_hash: None,
             setting,
             supported_blocks: vec![
                 BlockType {
@@ -225,6 +269,19 @@ impl ArchiveLedgerInfo {
             ],
         }
     }
+
+    pub fn admin_block_types() -> Vec<BlockType> {
+        vec![
+            BlockType {
+                block_type: "7admin_burn".into(),
+                url: "https://github.com/tuminfei/icrc7_launchpad".into(),
+            },
+            BlockType {
+                block_type: "7admin_xfer".into(),
+                url: "https://github.com/tuminfei/icrc7_launchpad".into(),
+            },
+        ]
+    }
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -275,6 +332,19 @@ pub struct InitArchiveArg {
 }
 
 impl InitArchiveArg {
+    pub fn from_archive_setting(setting: &ArchiveSetting) -> Self {
+        Self {
+            archive_controllers: setting.archive_controllers.clone(),
+            archive_cycles: setting.archive_cycles,
+            archive_index_type: setting.archive_index_type.clone(),
+            max_active_records: setting.max_active_records,
+            max_archive_pages: setting.max_archive_pages,
+            max_records_in_archive_instance: setting.max_records_in_archive_instance,
+            max_records_to_archive: setting.max_records_to_archive,
+            settle_to_records: setting.settle_to_records,
+        }
+    }
+
     pub fn to_archive_setting(self) -> ArchiveSetting {
         ArchiveSetting {
             archive_controllers: self.archive_controllers,
@@ -309,13 +379,6 @@ pub struct Tip {
     pub last_block_index: Vec<u8>,
 }
 
-#[derive(CandidType, Deserialize, Debug)]
-pub struct GetTransactionsResult {
-    pub blocks: Vec<ArchivedTransactionResponse>,
-    pub log_length: u128,
-    pub archived_blocks: Vec<u8>,
-}
-
 #[derive(CandidType, Deserialize, Debug)]
 pub struct GetBlocksResult {
     pub blocks: Vec<QueryBlock>,
@@ -398,7 +461,83 @@ pub struct ArchivedTransactionResponse {
     pub callback: QueryTransactionsFn,
 }
 
-pub type QueryTransactionsFn = GetTransactionsFn<Vec<QueryBlock>, GetTransactionsResult>;
+// Archived ranges are fetched with `icrc3_get_blocks` of the archive canister.
+pub type QueryTransactionsFn = GetTransactionsFn<Vec<TransactionRange>, GetBlocksResult>;
+
+// Splits the requested blocks below `log_length` between the `archives` holding them, one
+// response per archive with the ranges it is asked for.
+pub fn archived_blocks(
+    archives: &[(Principal, TransactionRange)],
+    args: &[TransactionRange],
+    log_length: u128,
+) -> Vec<ArchivedTransactionResponse> {
+    let mut archived_blocks: BTreeMap<Principal, ArchivedTransactionResponse> = BTreeMap::new();
+    for arg in args {
+        let end = arg.start.saturating_add(arg.length).min(log_length);
+        for (canister_id, range) in archives.iter() {
+            let overlap_start = arg.start.max(range.start);
+            let overlap_end = end.min(range.start + range.length);
+            if overlap_start >= overlap_end {
+                continue;
+            }
+            archived_blocks
+                .entry(*canister_id)
+                .or_insert_with(|| ArchivedTransactionResponse {
+                    args: vec![],
+                    callback: QueryTransactionsFn::new(*canister_id, "icrc3_get_blocks"),
+                })
+                .args
+                .push(TransactionRange {
+                    start: overlap_start,
+                    length: overlap_end - overlap_start,
+                });
+        }
+    }
+    archived_blocks.into_values().collect()
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveTip {
+    pub last_block_index: u128,
+    pub last_block_hash: Hash,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AppendBlocksError {
+    InsufficientCapacity {
+        remaining: u128,
+        requested: u128,
+    },
+    // The `phash` of the block `index` does not match the hash of the previous block.
+    InvalidParentHash {
+        index: u128,
+        expected: Option<Hash>,
+        found: Option<Hash>,
+    },
+    InsufficientMemory {
+        remaining_bytes: u64,
+        requested_bytes: u64,
+    },
+    MalformedBlock {
+        index: u128,
+    },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RemainingCapacity {
+    pub records: u64,
+    pub bytes: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveStats {
+    pub block_count: u64,
+    pub first_index: u128,
+    pub last_index: Option<u128>,
+    pub stable_memory_pages: u64,
+    pub max_pages: u128,
+    pub cycles: u128,
+}
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct GetArchivesResultItem {
@@ -409,8 +548,12 @@ pub struct GetArchivesResultItem {
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct ArchiveCreateArgs {
+    pub index_type: IndexType,
     pub max_pages: u128,
     pub max_records: u128,
     pub first_index: u128,
     pub controllers: Option<Option<Vec<Principal>>>,
+    pub supported_blocks: Vec<BlockType>,
+    // hash of the block `first_index - 1`, which the first appended block must link to
+    pub parent_hash: Option<Hash>,
 }
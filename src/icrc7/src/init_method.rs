@@ -1,14 +1,24 @@
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade};
-use ic_stable_structures::{writer::Writer, Memory};
 use icrc_ledger_types::icrc1::account::Account;
 
 use crate::{
-    icrc37_types::LedgerInfo, icrc3_types::ArchiveLedgerInfo, icrc7_types::InitArg, state::STATE,
+    icrc37_types::LedgerInfo,
+    icrc3_types::ArchiveLedgerInfo,
+    icrc7_types::LedgerArg,
+    state::{restore_state, save_state, set_certified_tip, STATE},
     utils::account_transformer,
 };
 
 #[init]
-pub fn init(arg: InitArg) {
+pub fn init(arg: LedgerArg) {
+    let arg = match arg {
+        LedgerArg::Init(arg) => arg,
+        LedgerArg::Upgrade(_) => ic_cdk::trap("cannot install the ledger with an Upgrade argument"),
+    };
+    if let Err(errors) = arg.validate() {
+        let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        ic_cdk::trap(&format!("invalid init argument: {}", errors.join("; ")));
+    }
     let minting_authority = account_transformer(match arg.minting_account {
         None => {
             let caller = ic_cdk::caller();
@@ -22,32 +32,19 @@ pub fn init(arg: InitArg) {
 
     let mut ledger_info = LedgerInfo::default();
     if let Some(approval_init) = arg.approval_init {
-        if let Some(max_approvals_per_token_or_collection) =
-            approval_init.max_approvals_per_token_or_collection
-        {
-            ledger_info.max_approvals_per_token_or_collection =
-                max_approvals_per_token_or_collection;
-        }
-        if let Some(max_revoke_approvals) = approval_init.max_revoke_approvals {
-            ledger_info.max_revoke_approvals = max_revoke_approvals;
-        }
-        if let Some(max_approvals) = approval_init.max_approvals {
-            ledger_info.max_approvals = max_approvals;
-        }
-        if let Some(settle_to_approvals) = approval_init.settle_to_approvals {
-            ledger_info.settle_to_approvals = settle_to_approvals;
-        }
-        if let Some(collection_approval_requires_token) =
-            approval_init.collection_approval_requires_token
-        {
-            ledger_info.collection_approval_requires_token = collection_approval_requires_token;
-        }
+        ledger_info.apply(approval_init);
     }
 
     let mut archive_ledger_info = ArchiveLedgerInfo::default();
     if let Some(archive_init) = arg.archive_init {
         archive_ledger_info = ArchiveLedgerInfo::new(Some(archive_init.to_archive_setting()))
     }
+    let admin_actions_enabled = arg.allow_admin_actions.unwrap_or(false);
+    if admin_actions_enabled {
+        archive_ledger_info
+            .supported_blocks
+            .extend(ArchiveLedgerInfo::admin_block_types());
+    }
 
     STATE.with(|s| {
         let mut s = s.borrow_mut();
@@ -56,6 +53,7 @@ pub fn init(arg: InitArg) {
         s.icrc7_name = arg.icrc7_name;
         s.icrc7_description = arg.icrc7_description;
         s.icrc7_logo = arg.icrc7_logo;
+        s.icrc7_asset_base_url = arg.icrc7_asset_base_url;
         s.icrc7_supply_cap = arg.icrc7_supply_cap;
         s.icrc7_max_query_batch_size = arg.icrc7_max_query_batch_size;
         s.icrc7_max_update_batch_size = arg.icrc7_max_update_batch_size;
@@ -67,42 +65,41 @@ pub fn init(arg: InitArg) {
         s.permitted_drift = arg.permitted_drift;
         s.approval_ledger_info = ledger_info;
         s.archive_ledger_info = archive_ledger_info;
+        s.burn_mode = arg.burn_mode.unwrap_or_default();
+        s.admin_actions_enabled = admin_actions_enabled;
     })
 }
 
 #[pre_upgrade]
 fn pre_upgrade() {
-    // Serialize the state.
-    // This example is using CBOR, but you can use any data format you like.
-    let mut state_bytes = vec![];
-    STATE
-        .with(|s| ciborium::ser::into_writer(&*s.borrow(), &mut state_bytes))
-        .expect("failed to encode state");
-
-    // Write the length of the serialized bytes to memory, followed by the
-    // by the bytes themselves.
-    let len = state_bytes.len() as u32;
-    let mut memory = crate::memory::get_upgrades_memory();
-    let mut writer = Writer::new(&mut memory, 0);
-    writer.write(&len.to_le_bytes()).unwrap();
-    writer.write(&state_bytes).unwrap();
+    save_state();
 }
 
-// A post-upgrade hook for deserializing the data back into the heap.
+// A post-upgrade hook restoring the config and applying the optional new settings.
 #[post_upgrade]
-fn post_upgrade() {
-    let memory = crate::memory::get_upgrades_memory();
-
-    // Read the length of the state bytes.
-    let mut state_len_bytes = [0; 4];
-    memory.read(0, &mut state_len_bytes);
-    let state_len = u32::from_le_bytes(state_len_bytes) as usize;
-
-    // Read the bytes
-    let mut state_bytes = vec![0; state_len];
-    memory.read(4, &mut state_bytes);
+fn post_upgrade(arg: Option<LedgerArg>) {
+    let upgrade_arg = match arg {
+        Some(LedgerArg::Init(_)) => ic_cdk::trap("cannot upgrade the ledger with an Init argument"),
+        Some(LedgerArg::Upgrade(upgrade_arg)) => upgrade_arg,
+        None => None,
+    };
+    restore_state();
+    if let Some(upgrade_arg) = upgrade_arg {
+        let with_approvals = upgrade_arg.approval_settings.is_some();
+        STATE.with(|s| s.borrow_mut().apply_upgrade_arg(upgrade_arg));
+        // the upgrade is rolled back unless `init` would accept the new settings
+        if let Err(errors) = STATE.with(|s| s.borrow().validate_config(with_approvals)) {
+            let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+            ic_cdk::trap(&format!("invalid upgrade argument: {}", errors.join("; ")));
+        }
+    }
 
-    // Deserialize and set the state.
-    let state = ciborium::de::from_reader(&*state_bytes).expect("failed to decode state");
-    STATE.with(|s| *s.borrow_mut() = state);
+    // The certification tree lives on the heap, rebuild it so the tip stays certified.
+    let tip = STATE.with(|s| {
+        let s = s.borrow();
+        s.last_block_index().zip(s.archive_ledger_info.latest_hash)
+    });
+    if let Some((last_block_index, last_block_hash)) = tip {
+        set_certified_tip(last_block_index, last_block_hash);
+    }
 }
@@ -18,6 +18,6 @@ pub mod guards;
 pub mod errors;
 pub mod archive;
 
-use icrc7_types::*;
+use crate::icrc7_types::*;
 
 export_candid!();
@@ -1,15 +1,20 @@
 use crate::{
     icrc37_types::{CollectionApprovalInfo, TokenApprovalInfo, UserAccount},
+    icrc3_types::TransactionRange,
     icrc7_types::Transaction,
     state::{Icrc7Token, MEMORY_MANAGER},
 };
+use candid::Principal;
 use ic_stable_structures::{
     memory_manager::{MemoryId, VirtualMemory},
     DefaultMemoryImpl, StableBTreeMap,
 };
 
 // A memory for upgrades, where data from the heap can be serialized/deserialized.
+// Only read to migrate the state written by releases before the versioned config.
 const UPGRADES: MemoryId = MemoryId::new(0);
+// The versioned config record saved on upgrade.
+const CONFIG: MemoryId = MemoryId::new(5);
 
 pub type Memory = VirtualMemory<DefaultMemoryImpl>;
 
@@ -33,3 +38,15 @@ pub fn get_collection_approvals_memory(
 ) -> StableBTreeMap<UserAccount, CollectionApprovalInfo, Memory> {
     StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))))
 }
+
+pub fn get_config_memory() -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(CONFIG))
+}
+
+pub fn get_archives_memory() -> StableBTreeMap<Principal, TransactionRange, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))))
+}
+
+pub fn get_sync_pending_memory() -> StableBTreeMap<u128, (), Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))))
+}
@@ -24,11 +24,21 @@ pub fn icrc7_logo() -> Option<String> {
     STATE.with(|s| s.borrow().icrc7_logo())
 }
 
+#[query]
+pub fn icrc7_asset_base_url() -> Option<String> {
+    STATE.with(|s| s.borrow().icrc7_asset_base_url())
+}
+
 #[query]
 pub fn icrc7_total_supply() -> u128 {
     STATE.with(|s| s.borrow().icrc7_total_supply())
 }
 
+#[query]
+pub fn burned_supply() -> u128 {
+    STATE.with(|s| s.borrow().burned_supply())
+}
+
 #[query]
 pub fn icrc7_supply_cap() -> Option<u128> {
     STATE.with(|s| s.borrow().icrc7_supply_cap())
@@ -91,6 +101,12 @@ pub fn icrc7_supported_standards() -> Vec<Standard> {
     ]
 }
 
+// ICRC-10 discovery, the same standards as `icrc7_supported_standards`
+#[query]
+pub fn icrc10_supported_standards() -> Vec<Standard> {
+    icrc7_supported_standards()
+}
+
 #[query]
 pub fn icrc7_tokens(prev: Option<u128>, take: Option<u128>) -> Vec<u128> {
     STATE.with(|s| s.borrow().icrc7_tokens(prev, take))
@@ -111,6 +127,11 @@ pub fn icrc7_tokens_of(account: Account, prev: Option<u128>, take: Option<u128>)
     STATE.with(|s| s.borrow().icrc7_tokens_of(account, prev, take))
 }
 
+#[query]
+pub fn admin_actions_enabled() -> bool {
+    STATE.with(|s| s.borrow().admin_actions_enabled)
+}
+
 #[query]
 pub fn minting_authority() -> Option<Account> {
     STATE.with(|s| s.borrow().icrc7_minting_authority())
@@ -1,4 +1,8 @@
-use std::{cell::RefCell, collections::BTreeMap, time::Duration};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
 
 use crate::{
     archive::create_archive_canister,
@@ -8,33 +12,37 @@ use crate::{
     },
     icrc37_types::{
         ApproveCollectionArg, ApproveCollectionResult, ApproveTokenArg, ApproveTokenResult,
-        CollectionApproval, CollectionApprovalInfo, IsApprovedArg, LedgerInfo, Metadata,
-        RevokeCollectionApprovalArg, RevokeCollectionApprovalResult, RevokeTokenApprovalArg,
-        RevokeTokenApprovalResult, TokenApproval, TokenApprovalInfo, TransferFromArg,
-        TransferFromResult, UserAccount,
+        CollectionApproval, CollectionApprovalInfo, InitApprovalsArg, IsApprovedArg, LedgerInfo,
+        Metadata, RevokeCollectionApprovalArg, RevokeCollectionApprovalResult,
+        RevokeTokenApprovalArg, RevokeTokenApprovalResult, TokenApproval, TokenApprovalInfo,
+        TransferFromArg, TransferFromResult, UserAccount,
     },
     icrc3_types::{
-        ArchiveCreateArgs, ArchiveLedgerInfo, ArchivedTransactionResponse, Block, GetArchiveArgs,
-        GetArchivesResultItem, GetBlocksArgs, GetBlocksResult, QueryBlock, QueryTransactionsFn,
-        Tip, TransactionRange,
+        archived_blocks, AppendBlocksError, ArchiveCreateArgs, ArchiveLedgerInfo, ArchiveTip,
+        Block, GetArchiveArgs, GetArchivesResultItem, GetBlocksArgs, GetBlocksResult,
+        InitArchiveArg, QueryBlock, RemainingCapacity, Tip, TransactionRange,
     },
     icrc7_types::{
-        BurnResult, Icrc7TokenMetadata, MintArg, MintResult, Transaction, TransactionType,
-        TransferArg, TransferResult,
+        AdminBurnArg, AdminTransferArg, BurnMode, BurnResult, FieldError, Icrc7TokenMetadata,
+        InitArg, MintArg, MintResult, Transaction, TransactionType, TransferArg, TransferResult,
+        UpgradeArg,
     },
     memory::{
-        get_collection_approvals_memory, get_log_memory, get_token_approvals_memory,
-        get_token_map_memory, Memory,
+        get_archives_memory, get_collection_approvals_memory, get_config_memory, get_log_memory,
+        get_sync_pending_memory, get_token_approvals_memory, get_token_map_memory,
+        get_upgrades_memory, Memory,
     },
-    utils::{account_transformer, burn_account, hash_icrc_value},
+    utils::{account_transformer, burn_account, hash_icrc_value, is_burn_account},
     BurnArg, SyncReceipt, TRANSACTION_TRANSFER_FROM_OP, TRANSACTION_TRANSFER_OP,
 };
 use candid::{CandidType, Decode, Encode, Principal};
 use ic_cdk_timers::TimerId;
-use ic_certified_map::{leaf_hash, AsHashTree, Hash, RbTree};
+use ic_certified_map::{AsHashTree, Hash, RbTree};
 use ic_stable_structures::{
-    memory_manager::MemoryManager, storable::Bound, DefaultMemoryImpl, StableBTreeMap, Storable,
+    memory_manager::MemoryManager, storable::Bound, DefaultMemoryImpl, Memory as _, StableBTreeMap,
+    StableCell, Storable,
 };
+use icrc7_types::certification::leb128_encode;
 use icrc_ledger_types::{
     icrc::generic_value::Value, icrc1::account::Account, icrc3::blocks::DataCertificate,
 };
@@ -101,13 +109,13 @@ impl Icrc7Token {
     }
 }
 
-#[derive(Serialize, Deserialize)]
 pub struct State {
     pub minting_authority: Option<Account>,
     pub icrc7_symbol: String,
     pub icrc7_name: String,
     pub icrc7_description: Option<String>,
     pub icrc7_logo: Option<String>,
+    pub icrc7_asset_base_url: Option<String>,
     pub icrc7_total_supply: u128,
     pub icrc7_supply_cap: Option<u128>,
     pub icrc7_max_query_batch_size: Option<u16>,
@@ -118,22 +126,22 @@ pub struct State {
     pub icrc7_atomic_batch_transfers: Option<bool>,
     pub tx_window: Option<u64>,
     pub permitted_drift: Option<u64>,
-    #[serde(skip, default = "get_token_map_memory")]
     pub tokens: StableBTreeMap<u128, Icrc7Token, Memory>,
     pub txn_count: u128,
     pub next_token_id: u128,
+    pub burn_mode: BurnMode,
+    pub burned_supply: u128,
+    pub admin_actions_enabled: bool,
 
     pub approval_ledger_info: LedgerInfo,
-    #[serde(skip, default = "get_token_approvals_memory")]
     pub token_approvals: StableBTreeMap<u128, TokenApprovalInfo, Memory>,
-    #[serde(skip, default = "get_collection_approvals_memory")]
     pub collection_approvals: StableBTreeMap<UserAccount, CollectionApprovalInfo, Memory>,
 
     pub archive_ledger_info: ArchiveLedgerInfo,
-    #[serde(skip, default = "get_log_memory")]
+    pub archives: StableBTreeMap<Principal, TransactionRange, Memory>,
     pub txn_ledger: StableBTreeMap<u128, Transaction, Memory>,
     pub archive_log_canister: Option<Principal>,
-    pub sync_pending_txn_ids: Option<Vec<u128>>,
+    pub sync_pending_txn_ids: StableBTreeMap<u128, (), Memory>,
     pub archive_txn_count: u128,
 }
 
@@ -145,6 +153,7 @@ impl Default for State {
             icrc7_name: "ICRC7 Collection".into(),
             icrc7_description: None,
             icrc7_logo: None,
+            icrc7_asset_base_url: None,
             icrc7_total_supply: 0,
             icrc7_supply_cap: None,
             icrc7_max_query_batch_size: None,
@@ -158,18 +167,199 @@ impl Default for State {
             tokens: get_token_map_memory(),
             txn_count: 0,
             next_token_id: 0,
+            burn_mode: BurnMode::default(),
+            burned_supply: 0,
+            admin_actions_enabled: false,
             txn_ledger: get_log_memory(),
             archive_log_canister: None,
-            sync_pending_txn_ids: None,
+            sync_pending_txn_ids: get_sync_pending_memory(),
             archive_txn_count: 0,
             approval_ledger_info: LedgerInfo::default(),
             token_approvals: get_token_approvals_memory(),
             collection_approvals: get_collection_approvals_memory(),
             archive_ledger_info: ArchiveLedgerInfo::default(),
+            archives: get_archives_memory(),
+        }
+    }
+}
+
+// Every layout the config was persisted with. Fields added with a serde default don't need
+// a new version; any other change to the persisted config adds a `StateV2` next to the
+// frozen `StateV1`, saves the new variant and migrates the old one in `into_state`.
+#[derive(Serialize, Deserialize)]
+pub enum VersionedState {
+    V1(StateV1),
+}
+
+impl VersionedState {
+    pub fn into_state(self) -> State {
+        match self {
+            VersionedState::V1(state) => state.into_state(),
+        }
+    }
+}
+
+// The config of `State` as persisted by the first versioned release, without the stable
+// structures. Frozen: it only changes by fields added with a serde default.
+#[derive(Serialize, Deserialize)]
+pub struct StateV1 {
+    pub minting_authority: Option<Account>,
+    pub icrc7_symbol: String,
+    pub icrc7_name: String,
+    pub icrc7_description: Option<String>,
+    pub icrc7_logo: Option<String>,
+    #[serde(default)]
+    pub icrc7_asset_base_url: Option<String>,
+    pub icrc7_total_supply: u128,
+    pub icrc7_supply_cap: Option<u128>,
+    pub icrc7_max_query_batch_size: Option<u16>,
+    pub icrc7_max_update_batch_size: Option<u16>,
+    pub icrc7_max_take_value: Option<u128>,
+    pub icrc7_default_take_value: Option<u128>,
+    pub icrc7_max_memo_size: Option<u32>,
+    pub icrc7_atomic_batch_transfers: Option<bool>,
+    pub tx_window: Option<u64>,
+    pub permitted_drift: Option<u64>,
+    pub txn_count: u128,
+    pub next_token_id: u128,
+    #[serde(default)]
+    pub burn_mode: BurnMode,
+    #[serde(default)]
+    pub burned_supply: u128,
+    #[serde(default)]
+    pub admin_actions_enabled: bool,
+    pub approval_ledger_info: LedgerInfo,
+    pub archive_ledger_info: ArchiveLedgerInfo,
+    pub archive_log_canister: Option<Principal>,
+    pub archive_txn_count: u128,
+}
+
+impl From<&State> for StateV1 {
+    fn from(state: &State) -> Self {
+        Self {
+            minting_authority: state.minting_authority,
+            icrc7_symbol: state.icrc7_symbol.clone(),
+            icrc7_name: state.icrc7_name.clone(),
+            icrc7_description: state.icrc7_description.clone(),
+            icrc7_logo: state.icrc7_logo.clone(),
+            icrc7_asset_base_url: state.icrc7_asset_base_url.clone(),
+            icrc7_total_supply: state.icrc7_total_supply,
+            icrc7_supply_cap: state.icrc7_supply_cap,
+            icrc7_max_query_batch_size: state.icrc7_max_query_batch_size,
+            icrc7_max_update_batch_size: state.icrc7_max_update_batch_size,
+            icrc7_max_take_value: state.icrc7_max_take_value,
+            icrc7_default_take_value: state.icrc7_default_take_value,
+            icrc7_max_memo_size: state.icrc7_max_memo_size,
+            icrc7_atomic_batch_transfers: state.icrc7_atomic_batch_transfers,
+            tx_window: state.tx_window,
+            permitted_drift: state.permitted_drift,
+            txn_count: state.txn_count,
+            next_token_id: state.next_token_id,
+            burn_mode: state.burn_mode,
+            burned_supply: state.burned_supply,
+            admin_actions_enabled: state.admin_actions_enabled,
+            approval_ledger_info: state.approval_ledger_info.clone(),
+            archive_ledger_info: state.archive_ledger_info.clone(),
+            archive_log_canister: state.archive_log_canister,
+            archive_txn_count: state.archive_txn_count,
         }
     }
 }
 
+impl StateV1 {
+    // Reopens the stable structures next to the restored config.
+    pub fn into_state(self) -> State {
+        State {
+            minting_authority: self.minting_authority,
+            icrc7_symbol: self.icrc7_symbol,
+            icrc7_name: self.icrc7_name,
+            icrc7_description: self.icrc7_description,
+            icrc7_logo: self.icrc7_logo,
+            icrc7_asset_base_url: self.icrc7_asset_base_url,
+            icrc7_total_supply: self.icrc7_total_supply,
+            icrc7_supply_cap: self.icrc7_supply_cap,
+            icrc7_max_query_batch_size: self.icrc7_max_query_batch_size,
+            icrc7_max_update_batch_size: self.icrc7_max_update_batch_size,
+            icrc7_max_take_value: self.icrc7_max_take_value,
+            icrc7_default_take_value: self.icrc7_default_take_value,
+            icrc7_max_memo_size: self.icrc7_max_memo_size,
+            icrc7_atomic_batch_transfers: self.icrc7_atomic_batch_transfers,
+            tx_window: self.tx_window,
+            permitted_drift: self.permitted_drift,
+            txn_count: self.txn_count,
+            next_token_id: self.next_token_id,
+            burn_mode: self.burn_mode,
+            burned_supply: self.burned_supply,
+            admin_actions_enabled: self.admin_actions_enabled,
+            approval_ledger_info: self.approval_ledger_info,
+            archive_ledger_info: self.archive_ledger_info,
+            archive_log_canister: self.archive_log_canister,
+            archive_txn_count: self.archive_txn_count,
+            ..State::default()
+        }
+    }
+}
+
+// The heap data of the releases before `VersionedState`, moved to stable structures since.
+#[derive(Deserialize)]
+struct LegacyState {
+    archive_ledger_info: LegacyArchiveLedgerInfo,
+    sync_pending_txn_ids: Option<Vec<u128>>,
+}
+
+#[derive(Deserialize)]
+struct LegacyArchiveLedgerInfo {
+    archives: BTreeMap<Principal, TransactionRange>,
+}
+
+fn config_cell() -> StableCell<Vec<u8>, Memory> {
+    StableCell::init(get_config_memory(), vec![]).expect("failed to init the config memory")
+}
+
+// Saves the config before an upgrade, tokens, approvals, blocks and archives
+// already live in stable structures and are left untouched.
+pub fn save_state() {
+    let state = STATE.with(|s| StateV1::from(&*s.borrow()));
+    let mut state_bytes = vec![];
+    ciborium::ser::into_writer(&VersionedState::V1(state), &mut state_bytes)
+        .expect("failed to encode state");
+    config_cell()
+        .set(state_bytes)
+        .expect("failed to save state");
+}
+
+// Reads the config back after an upgrade, migrating it from the layout it was saved with.
+pub fn restore_state() {
+    let state = if get_config_memory().size() == 0 {
+        restore_legacy_state()
+    } else {
+        let versioned: VersionedState = ciborium::de::from_reader(config_cell().get().as_slice())
+            .expect("failed to decode state");
+        versioned.into_state()
+    };
+    STATE.with(|s| *s.borrow_mut() = state);
+}
+
+// Releases before `VersionedState` wrote the whole state as length prefixed CBOR.
+fn restore_legacy_state() -> State {
+    let memory = get_upgrades_memory();
+    let mut state_len_bytes = [0; 4];
+    memory.read(0, &mut state_len_bytes);
+    let state_len = u32::from_le_bytes(state_len_bytes) as usize;
+    let mut state_bytes = vec![0; state_len];
+    memory.read(4, &mut state_bytes);
+
+    let config: StateV1 = ciborium::de::from_reader(&*state_bytes).expect("failed to decode state");
+    let mut state = config.into_state();
+    let legacy: LegacyState =
+        ciborium::de::from_reader(&*state_bytes).expect("failed to decode state");
+    for (canister_id, range) in legacy.archive_ledger_info.archives {
+        state.add_archive(canister_id, range);
+    }
+    state.set_sync_pending_txn_ids(legacy.sync_pending_txn_ids);
+    state
+}
+
 impl State {
     pub const DEFAULT_MAX_QUERY_BATCH_SIZE: u16 = 32;
     pub const DEFAULT_MAX_UPDATE_BATCH_SIZE: u16 = 32;
@@ -183,6 +373,100 @@ impl State {
         self.icrc7_symbol.clone()
     }
 
+    pub fn apply_upgrade_arg(&mut self, arg: UpgradeArg) {
+        if let Some(minting_account) = arg.minting_account {
+            self.minting_authority = Some(account_transformer(minting_account));
+        }
+        if let Some(icrc7_symbol) = arg.icrc7_symbol {
+            self.icrc7_symbol = icrc7_symbol;
+        }
+        if let Some(icrc7_name) = arg.icrc7_name {
+            self.icrc7_name = icrc7_name;
+        }
+        if arg.icrc7_description.is_some() {
+            self.icrc7_description = arg.icrc7_description;
+        }
+        if arg.icrc7_logo.is_some() {
+            self.icrc7_logo = arg.icrc7_logo;
+        }
+        if arg.icrc7_asset_base_url.is_some() {
+            self.icrc7_asset_base_url = arg.icrc7_asset_base_url;
+        }
+        if arg.icrc7_supply_cap.is_some() {
+            self.icrc7_supply_cap = arg.icrc7_supply_cap;
+        }
+        if arg.icrc7_max_query_batch_size.is_some() {
+            self.icrc7_max_query_batch_size = arg.icrc7_max_query_batch_size;
+        }
+        if arg.icrc7_max_update_batch_size.is_some() {
+            self.icrc7_max_update_batch_size = arg.icrc7_max_update_batch_size;
+        }
+        if arg.icrc7_max_take_value.is_some() {
+            self.icrc7_max_take_value = arg.icrc7_max_take_value;
+        }
+        if arg.icrc7_default_take_value.is_some() {
+            self.icrc7_default_take_value = arg.icrc7_default_take_value;
+        }
+        if arg.icrc7_max_memo_size.is_some() {
+            self.icrc7_max_memo_size = arg.icrc7_max_memo_size;
+        }
+        if arg.icrc7_atomic_batch_transfers.is_some() {
+            self.icrc7_atomic_batch_transfers = arg.icrc7_atomic_batch_transfers;
+        }
+        if arg.tx_window.is_some() {
+            self.tx_window = arg.tx_window;
+        }
+        if arg.permitted_drift.is_some() {
+            self.permitted_drift = arg.permitted_drift;
+        }
+        if let Some(approval_settings) = arg.approval_settings {
+            self.approval_ledger_info.apply(approval_settings);
+        }
+        if let Some(archive_settings) = arg.archive_settings {
+            self.archive_ledger_info.setting = archive_settings.to_archive_setting();
+        }
+    }
+
+    // Checks the current settings with the validator `init` runs. The approval limits are
+    // only checked when `with_approvals`, the defaults of the ledger predate the validator.
+    pub fn validate_config(&self, with_approvals: bool) -> Result<(), Vec<FieldError>> {
+        let approvals = &self.approval_ledger_info;
+        InitArg {
+            minting_account: self.minting_authority,
+            icrc7_symbol: self.icrc7_symbol.clone(),
+            icrc7_name: self.icrc7_name.clone(),
+            icrc7_description: self.icrc7_description.clone(),
+            icrc7_logo: self.icrc7_logo.clone(),
+            icrc7_asset_base_url: self.icrc7_asset_base_url.clone(),
+            icrc7_supply_cap: self.icrc7_supply_cap,
+            icrc7_max_query_batch_size: self.icrc7_max_query_batch_size,
+            icrc7_max_update_batch_size: self.icrc7_max_update_batch_size,
+            icrc7_max_take_value: self.icrc7_max_take_value,
+            icrc7_default_take_value: self.icrc7_default_take_value,
+            icrc7_max_memo_size: self.icrc7_max_memo_size,
+            icrc7_atomic_batch_transfers: self.icrc7_atomic_batch_transfers,
+            tx_window: self.tx_window,
+            permitted_drift: self.permitted_drift,
+            approval_init: with_approvals.then_some(InitApprovalsArg {
+                max_approvals: Some(approvals.max_approvals),
+                max_approvals_per_token_or_collection: Some(
+                    approvals.max_approvals_per_token_or_collection,
+                ),
+                max_revoke_approvals: Some(approvals.max_revoke_approvals),
+                settle_to_approvals: Some(approvals.settle_to_approvals),
+                collection_approval_requires_token: Some(
+                    approvals.collection_approval_requires_token,
+                ),
+            }),
+            archive_init: Some(InitArchiveArg::from_archive_setting(
+                &self.archive_ledger_info.setting,
+            )),
+            burn_mode: Some(self.burn_mode),
+            allow_admin_actions: Some(self.admin_actions_enabled),
+        }
+        .validate()
+    }
+
     pub fn icrc7_name(&self) -> String {
         self.icrc7_name.clone()
     }
@@ -203,6 +487,19 @@ impl State {
         self.icrc7_logo.clone()
     }
 
+    pub fn icrc7_asset_base_url(&self) -> Option<String> {
+        self.icrc7_asset_base_url.clone()
+    }
+
+    pub fn burned_supply(&self) -> u128 {
+        self.burned_supply
+    }
+
+    // Normalized like the caller account `mint` compares it with.
+    pub fn set_minting_authority(&mut self, minting_account: Account) {
+        self.minting_authority = Some(account_transformer(minting_account));
+    }
+
     pub fn icrc7_minting_authority(&self) -> Option<Account> {
         self.minting_authority.clone()
     }
@@ -234,7 +531,7 @@ impl State {
     pub fn icrc7_owner_of(&self, token_id: &[u128]) -> Vec<Option<Account>> {
         let mut res = vec![None; token_id.len()];
         for (index, id) in token_id.iter().enumerate() {
-            if let Some(ref token) = self.tokens.get(id) {
+            if let Some(ref token) = self.get_token(id) {
                 res[index] = Some(token.token_owner);
             }
         }
@@ -267,19 +564,50 @@ impl State {
         res
     }
 
+    // Returns the token unless it does not exist or has been burned.
+    // Tokens burned in `BurnMode::BurnAddress` stay in `tokens` but are owned by the burn account.
+    fn get_token(&self, token_id: &u128) -> Option<Icrc7Token> {
+        self.tokens
+            .get(token_id)
+            .filter(|token| !Self::is_burned(token))
+    }
+
+    fn is_burned(token: &Icrc7Token) -> bool {
+        is_burn_account(&token.token_owner)
+    }
+
+    fn live_tokens(&self) -> impl Iterator<Item = (u128, Icrc7Token)> + '_ {
+        self.tokens
+            .iter()
+            .filter(|(_, token)| !Self::is_burned(token))
+    }
+
     pub fn get_archive_log_canister(&self) -> Option<Principal> {
         self.archive_log_canister
     }
 
     pub fn get_sync_pending_txn_ids(&self) -> Option<Vec<u128>> {
-        self.sync_pending_txn_ids.clone()
+        if self.sync_pending_txn_ids.is_empty() {
+            return None;
+        }
+        Some(self.sync_pending_txn_ids.iter().map(|(id, _)| id).collect())
     }
 
     pub fn set_sync_pending_txn_ids(&mut self, txn_ids: Option<Vec<u128>>) -> bool {
-        self.sync_pending_txn_ids = txn_ids;
+        self.clear_sync_pending_txn_ids();
+        for txn_id in txn_ids.unwrap_or_default() {
+            self.sync_pending_txn_ids.insert(txn_id, ());
+        }
         return true;
     }
 
+    fn clear_sync_pending_txn_ids(&mut self) {
+        let txn_ids: Vec<u128> = self.sync_pending_txn_ids.iter().map(|(id, _)| id).collect();
+        for txn_id in txn_ids {
+            self.sync_pending_txn_ids.remove(&txn_id);
+        }
+    }
+
     fn txn_deduplication_check(
         &self,
         allowed_past_time: &u64,
@@ -299,7 +627,7 @@ impl State {
                     && caller == txn.from.as_ref().unwrap()
                     && args.to == txn.to.unwrap()
                     && args.memo == txn.memo
-                    && args.created_at_time == Some(txn.ts)
+                    && args.created_at_time == txn.created_at_time
                 {
                     return Err(TransferError::Duplicate {
                         duplicate_of: count,
@@ -325,10 +653,11 @@ impl State {
     fn log_transaction(
         &mut self,
         txn_type: TransactionType,
-        at: u64,
+        created_at_time: Option<u64>,
         memo: Option<Vec<u8>>,
     ) -> u128 {
         let txn_id = self.get_txn_id();
+        let at = ic_cdk::api::time();
 
         // Get the information of the previous transaction.
         // let current_size = self.archive_ledger_info.local_ledger_size;
@@ -339,6 +668,7 @@ impl State {
         // };
 
         let mut txn = Transaction::new(txn_id, txn_type, at, memo);
+        txn.created_at_time = created_at_time;
         let phash = self.archive_ledger_info.latest_hash;
 
         let block = Block::new(phash, txn.clone());
@@ -350,16 +680,7 @@ impl State {
         self.archive_ledger_info.latest_hash = Some(block_hash);
         self.archive_ledger_info.local_ledger_size += 1;
 
-        // set certified data
-        TREE.with(|tree| {
-            let mut tree = tree.borrow_mut();
-            tree.insert(
-                "last_block_index",
-                leaf_hash(&self.archive_ledger_info.last_index.to_be_bytes()),
-            );
-            tree.insert("last_block_hash", leaf_hash(&block_hash));
-            ic_cdk::api::set_certified_data(&tree.root_hash());
-        });
+        set_certified_tip(txn_id, block_hash);
 
         if self.archive_ledger_info.local_ledger_size
             > self.archive_ledger_info.setting.max_active_records
@@ -452,7 +773,7 @@ impl State {
             self.txn_deduplication_check(&allowed_past_time, caller, arg)?;
         }
         // checking is token for the corresponding ID exists or not
-        if let None = self.tokens.get(&arg.token_id) {
+        if let None = self.get_token(&arg.token_id) {
             return Err(TransferError::NonExistingTokenId);
         }
         if let Some(ref memo) = arg.memo {
@@ -467,10 +788,10 @@ impl State {
             }
         }
         // checking if receiver and sender have same address
-        if arg.to == *caller {
+        if arg.to == *caller || is_burn_account(&arg.to) {
             return Err(TransferError::InvalidRecipient);
         }
-        let token = self.tokens.get(&arg.token_id).unwrap();
+        let token = self.get_token(&arg.token_id).unwrap();
         // checking if the caller is authorized or is approve to make transaction
         if token.token_owner != *caller {
             return Err(TransferError::Unauthorized);
@@ -532,7 +853,6 @@ impl State {
                 owner: caller.clone(),
                 subaccount: arg.from_subaccount,
             });
-            let time = arg.created_at_time.unwrap_or(current_time);
             if let Some(Err(e)) = txn_results.get(index).unwrap() {
                 match e {
                     TransferError::GenericBatchError {
@@ -551,7 +871,7 @@ impl State {
                     from: caller_account.clone(),
                     to: arg.to.clone(),
                 },
-                time,
+                arg.created_at_time,
                 arg.memo.clone(),
             );
             txn_results[index] = Some(Ok(txn_id));
@@ -574,6 +894,12 @@ impl State {
         if Some(*caller) != self.minting_authority {
             return Err(MintError::Unauthorized);
         }
+        if is_burn_account(&arg.to) {
+            return Err(MintError::GenericError {
+                error_code: 12,
+                message: "Invalid Recipient".into(),
+            });
+        }
         if let Some(ref memo) = arg.memo {
             let allowed_memo_length = self
                 .icrc7_max_memo_size
@@ -624,7 +950,7 @@ impl State {
                 to: arg.to,
                 meta: token_metadata,
             },
-            ic_cdk::api::time(),
+            None,
             arg.memo,
         );
         Ok(txn_id)
@@ -643,7 +969,7 @@ impl State {
                 });
             }
         }
-        match self.tokens.get(&arg.token_id) {
+        match self.get_token(&arg.token_id) {
             None => Err(BurnError::NonExistingTokenId),
             Some(ref token) => {
                 if token.token_owner != *caller {
@@ -654,7 +980,28 @@ impl State {
         }
     }
 
-    pub fn burn(&mut self, caller: &Principal, mut args: Vec<BurnArg>) -> Vec<Option<BurnResult>> {
+    // Checks every burn of a batch against the state before the batch. A token id repeated
+    // in the batch fails after its first occurrence, so that it is burned once.
+    fn check_burns(&self, caller: &Principal, args: &[BurnArg]) -> Vec<Result<(), BurnError>> {
+        let mut token_ids = BTreeSet::new();
+        args.iter()
+            .map(|arg| {
+                if !token_ids.insert(arg.token_id) {
+                    return Err(BurnError::GenericError {
+                        error_code: 11,
+                        message: "Duplicate Token Id In Batch".into(),
+                    });
+                }
+                let caller = account_transformer(Account {
+                    owner: *caller,
+                    subaccount: arg.from_subaccount,
+                });
+                self.mock_burn(&caller, arg)
+            })
+            .collect()
+    }
+
+    pub fn burn(&mut self, caller: &Principal, args: Vec<BurnArg>) -> Vec<Option<BurnResult>> {
         if args.len() == 0 {
             return vec![Some(Err(BurnError::GenericBatchError {
                 error_code: 1,
@@ -669,13 +1016,9 @@ impl State {
             }));
             return txn_results;
         }
-        for (index, arg) in args.iter_mut().enumerate() {
-            let caller = account_transformer(Account {
-                owner: caller.clone(),
-                subaccount: arg.from_subaccount,
-            });
-            if let Err(e) = self.mock_burn(&caller, arg) {
-                txn_results.insert(index, Some(Err(e)))
+        for (index, result) in self.check_burns(caller, &args).into_iter().enumerate() {
+            if let Err(e) = result {
+                txn_results[index] = Some(Err(e));
             }
         }
         if let Some(true) = self.icrc7_atomic_batch_transfers {
@@ -691,7 +1034,6 @@ impl State {
                 owner: caller.clone(),
                 subaccount: arg.from_subaccount,
             });
-            let burn_address = burn_account();
             if let Some(Err(e)) = txn_results.get(index).unwrap() {
                 match e {
                     BurnError::GenericBatchError {
@@ -701,23 +1043,163 @@ impl State {
                     _ => continue,
                 }
             }
-            let mut token = self.tokens.get(&arg.token_id).unwrap();
-            token.burn(burn_address.clone());
-            self.tokens.insert(arg.token_id, token);
+            let burn_address = self.burn_token(arg.token_id);
             let tid = self.log_transaction(
                 TransactionType::Burn {
                     tid: arg.token_id,
                     from: caller,
                     to: burn_address,
                 },
-                ic_cdk::api::time(),
+                None,
                 arg.memo.clone(),
             );
-            txn_results.insert(index, Some(Ok(tid)))
+            txn_results[index] = Some(Ok(tid));
         }
         txn_results
     }
 
+    // Burns the token according to the configured `BurnMode` and returns the burn address, if any.
+    fn burn_token(&mut self, token_id: u128) -> Option<Account> {
+        let burn_address = match self.burn_mode {
+            BurnMode::Tombstone => {
+                // the id stays reserved because minting requires `token_id >= next_token_id`
+                self.tokens.remove(&token_id);
+                None
+            }
+            BurnMode::BurnAddress => {
+                let burn_address = burn_account();
+                let mut token = self.tokens.get(&token_id).unwrap();
+                token.burn(burn_address);
+                self.tokens.insert(token_id, token);
+                Some(burn_address)
+            }
+        };
+        self.token_approvals_clean(&token_id);
+        self.icrc7_total_supply -= 1;
+        self.burned_supply += 1;
+        burn_address
+    }
+
+    fn mock_admin_burn(
+        &self,
+        caller: &Account,
+        arg: &AdminBurnArg,
+    ) -> Result<Icrc7Token, BurnError> {
+        if !self.admin_actions_enabled {
+            return Err(BurnError::GenericError {
+                error_code: 9,
+                message: "Admin Actions Disabled".into(),
+            });
+        }
+        if Some(*caller) != self.minting_authority {
+            return Err(BurnError::Unauthorized);
+        }
+        if arg.memo.is_empty() {
+            return Err(BurnError::GenericError {
+                error_code: 10,
+                message: "Reason Memo Required".into(),
+            });
+        }
+        if arg.memo.len() as u32
+            > self
+                .icrc7_max_memo_size
+                .unwrap_or(State::DEFAULT_MAX_MEMO_SIZE)
+        {
+            return Err(BurnError::GenericError {
+                error_code: 3,
+                message: "Exceeds Max Memo Length".into(),
+            });
+        }
+        self.get_token(&arg.token_id)
+            .ok_or(BurnError::NonExistingTokenId)
+    }
+
+    pub fn admin_burn(&mut self, caller: &Principal, arg: AdminBurnArg) -> BurnResult {
+        let caller = account_transformer(Account {
+            owner: *caller,
+            subaccount: arg.from_subaccount,
+        });
+        let token = self.mock_admin_burn(&caller, &arg)?;
+        let burn_address = self.burn_token(arg.token_id);
+        let txn_id = self.log_transaction(
+            TransactionType::AdminBurn {
+                tid: arg.token_id,
+                from: token.token_owner,
+                to: burn_address,
+                authority: caller,
+            },
+            None,
+            Some(arg.memo),
+        );
+        Ok(txn_id)
+    }
+
+    fn mock_admin_transfer(
+        &self,
+        caller: &Account,
+        arg: &AdminTransferArg,
+    ) -> Result<Icrc7Token, TransferError> {
+        if !self.admin_actions_enabled {
+            return Err(TransferError::GenericError {
+                error_code: 9,
+                message: "Admin Actions Disabled".into(),
+            });
+        }
+        if Some(*caller) != self.minting_authority {
+            return Err(TransferError::Unauthorized);
+        }
+        if arg.memo.is_empty() {
+            return Err(TransferError::GenericError {
+                error_code: 10,
+                message: "Reason Memo Required".into(),
+            });
+        }
+        let max_memo_size = self
+            .icrc7_max_memo_size
+            .unwrap_or(State::DEFAULT_MAX_MEMO_SIZE);
+        if arg.memo.len() as u32 > max_memo_size {
+            return Err(TransferError::GenericError {
+                error_code: 3,
+                message: "Exceeds Max Memo Size".into(),
+            });
+        }
+        let token = self
+            .get_token(&arg.token_id)
+            .ok_or(TransferError::NonExistingTokenId)?;
+        if arg.to == token.token_owner || is_burn_account(&arg.to) {
+            return Err(TransferError::InvalidRecipient);
+        }
+        Ok(token)
+    }
+
+    pub fn admin_transfer(
+        &mut self,
+        caller: &Principal,
+        mut arg: AdminTransferArg,
+    ) -> TransferResult {
+        let caller = account_transformer(Account {
+            owner: *caller,
+            subaccount: arg.from_subaccount,
+        });
+        arg.to = account_transformer(arg.to);
+        let mut token = self.mock_admin_transfer(&caller, &arg)?;
+        let from = token.token_owner;
+        token.transfer(arg.to);
+        self.tokens.insert(arg.token_id, token);
+        self.token_approvals_clean(&arg.token_id);
+        let txn_id = self.log_transaction(
+            TransactionType::AdminTransfer {
+                tid: arg.token_id,
+                from,
+                to: arg.to,
+                authority: caller,
+            },
+            None,
+            Some(arg.memo),
+        );
+        Ok(txn_id)
+    }
+
     fn mock_approve(
         &self,
         caller: &Account,
@@ -737,7 +1219,7 @@ impl State {
                 });
             }
         };
-        match self.tokens.get(&arg.token_id) {
+        match self.get_token(&arg.token_id) {
             None => Err(ApproveTokenError::NonExistingTokenId),
             Some(ref token) => {
                 if token.token_owner != *caller {
@@ -821,7 +1303,7 @@ impl State {
                     to: arg.approval_info.spender,
                     exp_sec: arg.approval_info.expires_at,
                 },
-                ic_cdk::api::time(),
+                arg.approval_info.created_at_time,
                 arg.approval_info.memo.clone(),
             );
             txn_results.insert(index, Some(Ok(tid)))
@@ -937,7 +1419,7 @@ impl State {
                     to: arg.approval_info.spender,
                     exp_sec: arg.approval_info.expires_at,
                 },
-                ic_cdk::api::time(),
+                arg.approval_info.created_at_time,
                 arg.approval_info.memo.clone(),
             );
             txn_results.insert(index, Some(Ok(tid)))
@@ -972,7 +1454,7 @@ impl State {
             }
         };
 
-        match self.tokens.get(&arg.token_id) {
+        match self.get_token(&arg.token_id) {
             None => Err(RevokeTokenApprovalError::NonExistingTokenId),
             Some(ref token) => {
                 if token.token_owner != *caller {
@@ -1054,7 +1536,7 @@ impl State {
                     from: caller,
                     to: arg.spender,
                 },
-                ic_cdk::api::time(),
+                arg.created_at_time,
                 arg.memo.clone(),
             );
             txn_results.insert(index, Some(Ok(tid)))
@@ -1176,7 +1658,7 @@ impl State {
                     from: caller,
                     to: arg.spender,
                 },
-                ic_cdk::api::time(),
+                arg.created_at_time,
                 arg.memo.clone(),
             );
             txn_results.insert(index, Some(Ok(tid)))
@@ -1196,6 +1678,13 @@ impl State {
                 message: "Spender cannot be caller".into(),
             });
         }
+        if is_burn_account(&arg.to) {
+            return Err(TransferFromError::InvalidRecipient);
+        }
+
+        if self.get_token(&arg.token_id).is_none() {
+            return Err(TransferFromError::NonExistingTokenId);
+        }
 
         if let Some(time) = arg.created_at_time {
             let allowed_past_time = *current_time
@@ -1294,7 +1783,6 @@ impl State {
                 owner: caller.clone(),
                 subaccount: arg.spender_subaccount,
             });
-            let time = arg.created_at_time.unwrap_or(current_time);
             if let Some(Err(e)) = txn_results.get(index).unwrap() {
                 match e {
                     TransferFromError::GenericBatchError {
@@ -1315,7 +1803,7 @@ impl State {
                     to: arg.to.clone(),
                     spender: caller_account.clone(),
                 },
-                time,
+                arg.created_at_time,
                 arg.memo.clone(),
             );
             txn_results[index] = Some(Ok(txn_id));
@@ -1332,7 +1820,7 @@ impl State {
     ) -> Vec<TokenApproval> {
         let take = self.get_current_take(take);
         let mut results: Vec<TokenApproval> = vec![];
-        let token = match self.tokens.get(&token_id) {
+        let token = match self.get_token(&token_id) {
             Some(token) => token,
             None => return results,
         };
@@ -1440,7 +1928,7 @@ impl State {
         }
         let mut metadata_list = vec![None; token_ids.len()];
         for (index, tid) in token_ids.iter().enumerate() {
-            if let Some(ref token) = self.tokens.get(tid) {
+            if let Some(ref token) = self.get_token(tid) {
                 metadata_list[index] = Some(token.token_metadata());
             }
         }
@@ -1450,7 +1938,7 @@ impl State {
     pub fn icrc7_balance_of(&self, accounts: &[Account]) -> Vec<u128> {
         let mut count_list = vec![0; accounts.len()];
         accounts.iter().enumerate().for_each(|(index, account)| {
-            self.tokens.iter().for_each(|(_id, ref token)| {
+            self.live_tokens().for_each(|(_id, ref token)| {
                 if token.token_owner == *account {
                     let current_count = count_list[index];
                     count_list[index] = current_count + 1;
@@ -1466,7 +1954,7 @@ impl State {
             ic_cdk::trap("Exceeds Max Take Value")
         }
 
-        let mut list: Vec<u128> = self.tokens.iter().map(|(k, _)| k).collect();
+        let mut list: Vec<u128> = self.live_tokens().map(|(k, _)| k).collect();
         list.sort();
 
         take = std::cmp::min(take, list.len() as u128);
@@ -1496,7 +1984,7 @@ impl State {
             ic_cdk::trap("Exceeds Max Take Value")
         }
         let mut owned_tokens = vec![];
-        for (id, token) in self.tokens.iter() {
+        for (id, token) in self.live_tokens() {
             if token.token_owner == account {
                 owned_tokens.push(id);
             }
@@ -1532,140 +2020,47 @@ impl State {
         tx_logs
     }
 
+    // Index of the last block of the chain, `None` if no block has been recorded yet.
+    pub fn last_block_index(&self) -> Option<u128> {
+        self.txn_count.checked_sub(1)
+    }
+
     pub fn icrc3_get_tip_certificate(&self) -> Option<DataCertificate> {
+        self.archive_ledger_info.latest_hash?;
         let certificate = ic_cdk::api::data_certificate();
         let certificate_buf: Option<ByteBuf> = certificate.map(|vec| ByteBuf::from(vec));
-        let witness = TREE.with(|tree| {
-            let tree = tree.borrow();
-            let mut witness = vec![];
-            let mut witness_serializer = serde_cbor::Serializer::new(&mut witness);
-            let _ = witness_serializer.self_describe();
-            tree.witness(b"last_block_index")
-                .serialize(&mut witness_serializer)
-                .unwrap();
-            tree.witness(b"last_block_hash")
-                .serialize(&mut witness_serializer)
-                .unwrap();
-            witness
-        });
         return Some(DataCertificate {
             certificate: certificate_buf,
-            hash_tree: ByteBuf::from(witness),
+            hash_tree: ByteBuf::from(tip_witness()),
         });
     }
 
+    // Block ids are the global transaction ids: the ledger keeps `first_index..txn_count`
+    // locally and every archive keeps the ids of its `TransactionRange`.
     pub fn icrc3_get_blocks(&self, args: GetBlocksArgs) -> GetBlocksResult {
-        let local_ledger_length = self.txn_ledger.len() as u128;
         let local_first_index = self.archive_ledger_info.first_index;
-        let local_last_index = self.archive_ledger_info.last_index;
-
-        let ledger_length = if local_last_index == 0 && local_ledger_length == 0 {
-            0
-        } else {
-            local_last_index + 1
-        };
+        let log_length = self.txn_count;
 
         let mut local_blocks: Vec<QueryBlock> = vec![];
-        let mut archived_blocks: BTreeMap<Principal, ArchivedTransactionResponse> = BTreeMap::new();
-
-        //get the transactions on this canister
-        for arg in args.clone() {
-            if arg.start + arg.length > local_first_index {
-                let start = if arg.start <= local_first_index {
-                    0
-                } else {
-                    arg.start - local_first_index
-                };
-
-                let end = if local_ledger_length == 0 {
-                    0
-                } else if arg.start + arg.length >= local_first_index {
-                    local_ledger_length - 1
-                } else {
-                    local_last_index
-                        - local_first_index
-                        - (local_last_index - (arg.start + arg.length))
-                };
-
-                if local_ledger_length > 0 {
-                    for this_item in start..=end {
-                        let tx_id = local_first_index + this_item;
-                        let block_info = self.txn_ledger.get(&tx_id).unwrap().block.unwrap();
-                        if this_item >= local_ledger_length {
-                            break;
-                        }
-                        local_blocks.push(QueryBlock {
-                            id: tx_id,
-                            block: block_info.into_inner(),
-                        });
-                    }
-                }
-            }
-        }
-
-        //get any archive transactions
-        for arg in args {
-            let mut seeking = arg.start;
-            for (key, tran_range) in &self.archive_ledger_info.archives {
-                if (seeking > tran_range.start + tran_range.length - 1)
-                    || (arg.start + arg.length <= tran_range.start)
-                {
-                    continue;
-                };
-
-                // Calculate the start and end indices of the intersection between the requested range and the current archive.
-                let overlap_start = std::cmp::max(seeking, tran_range.start);
-                let overlap_end = std::cmp::min(
-                    arg.start + arg.length - 1,
-                    tran_range.start + tran_range.length - 1,
-                );
-                let overlap_length = overlap_end - overlap_start + 1;
-
-                match archived_blocks.get_mut(key) {
-                    Some(archive) => {
-                        archive.args.push(TransactionRange {
-                            start: overlap_start,
-                            length: overlap_length,
-                        });
-                    }
-                    None => {
-                        archived_blocks.insert(
-                            *key,
-                            ArchivedTransactionResponse {
-                                args: vec![TransactionRange {
-                                    start: overlap_start,
-                                    length: overlap_length,
-                                }],
-                                callback: QueryTransactionsFn {
-                                    canister_id: *key,
-                                    method: "get_transactions".to_string(),
-                                    _marker: std::marker::PhantomData,
-                                },
-                            },
-                        );
-                    }
+        for arg in args.iter() {
+            let end = arg.start.saturating_add(arg.length).min(log_length);
+
+            //get the transactions on this canister
+            for id in arg.start.max(local_first_index)..end {
+                if let Some(block) = self.txn_ledger.get(&id).and_then(|txn| txn.block) {
+                    local_blocks.push(QueryBlock {
+                        id,
+                        block: block.into_inner(),
+                    });
                 }
-
-                // If the overlap ends exactly where the requested range ends, break out of the loop.
-                if overlap_end == arg.start + arg.length - 1 {
-                    break;
-                };
-
-                // Update seeking to the next desired transaction.
-                seeking = overlap_end + 1;
             }
         }
 
-        let archived_blocks_vec: Vec<ArchivedTransactionResponse> = archived_blocks
-            .into_iter()
-            .map(|(_, value)| value)
-            .collect();
-
-        return GetBlocksResult {
+        GetBlocksResult {
             blocks: local_blocks,
-            log_length: ledger_length,
-            archived_blocks: archived_blocks_vec,
-        };
+            log_length,
+            archived_blocks: archived_blocks(&self.sorted_archives(), &args, log_length),
+        }
     }
 
     pub fn icrc3_get_archives(&self, arg: GetArchiveArgs) -> Vec<GetArchivesResultItem> {
@@ -1680,7 +2075,7 @@ impl State {
             results.push(GetArchivesResultItem {
                 canister_id,
                 start: self.archive_ledger_info.first_index,
-                end: self.archive_ledger_info.last_index,
+                end: self.txn_count.saturating_sub(1),
             })
         } else {
             if let Some(from) = arg.from {
@@ -1690,18 +2085,20 @@ impl State {
             }
         }
 
-        for (principal, range) in self.archive_ledger_info.archives.iter() {
+        let archives = self.sorted_archives();
+
+        for (principal, range) in archives {
             if is_found {
-                if range.start + range.length >= 1 {
+                if range.length >= 1 {
                     results.push(GetArchivesResultItem {
-                        canister_id: *principal,
+                        canister_id: principal,
                         start: range.start,
-                        end: range.start + range.length,
+                        end: range.start + range.length - 1,
                     })
                 }
             } else {
                 if let Some(from) = arg.from {
-                    if from == *principal {
+                    if from == principal {
                         is_found = true;
                     }
                 }
@@ -1714,23 +2111,10 @@ impl State {
         if self.archive_ledger_info.latest_hash.is_none() {
             ic_cdk::trap("No root")
         }
-        let witness = TREE.with(|tree| {
-            let tree = tree.borrow();
-            let mut witness = vec![];
-            let mut witness_serializer = serde_cbor::Serializer::new(&mut witness);
-            let _ = witness_serializer.self_describe();
-            tree.witness(b"last_block_index")
-                .serialize(&mut witness_serializer)
-                .unwrap();
-            tree.witness(b"last_block_hash")
-                .serialize(&mut witness_serializer)
-                .unwrap();
-            witness
-        });
         return Tip {
             last_block_hash: self.archive_ledger_info.latest_hash.unwrap(),
-            last_block_index: self.archive_ledger_info.last_index.to_be_bytes().to_vec(),
-            hash_tree: witness,
+            last_block_index: leb128_encode(self.last_block_index().unwrap_or_default()),
+            hash_tree: tip_witness(),
         };
     }
 
@@ -1749,7 +2133,7 @@ impl State {
         for txn_id in txn_ids {
             self.txn_ledger.remove(txn_id);
         }
-        self.sync_pending_txn_ids = None;
+        self.clear_sync_pending_txn_ids();
         self.archive_txn_count += txn_ids.len() as u128;
         return true;
     }
@@ -1762,19 +2146,68 @@ impl State {
         return to_archive;
     }
 
+    // Encoded size of the oldest `size` local blocks, as an archive stores them.
+    pub fn archive_batch_bytes(&self, size: usize) -> u64 {
+        self.txn_ledger
+            .iter()
+            .take(size)
+            .filter_map(|(_, txn)| txn.block)
+            .map(|block| block.to_bytes().len() as u64)
+            .sum()
+    }
+
     pub fn add_archive(&mut self, canister_id: Principal, range: TransactionRange) -> bool {
-        self.archive_ledger_info.archives.insert(canister_id, range);
+        self.archives.insert(canister_id, range);
         return true;
     }
+
+    // The archive canisters ordered by the first block id they hold.
+    pub fn sorted_archives(&self) -> Vec<(Principal, TransactionRange)> {
+        let mut archives: Vec<(Principal, TransactionRange)> = self.archives.iter().collect();
+        archives.sort_by_key(|(_, range)| range.start);
+        archives
+    }
+
+    pub fn extend_archive(&mut self, canister_id: Principal, length: u128) {
+        if let Some(mut range) = self.archives.get(&canister_id) {
+            range.length += length;
+            self.archives.insert(canister_id, range);
+        }
+    }
 }
 
 thread_local! {
     pub static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
     pub static STATE: RefCell<State> = RefCell::default();
-    pub static TREE: RefCell<RbTree<&'static str, Hash>> = RefCell::new(RbTree::new());
+    pub static TREE: RefCell<RbTree<&'static str, Vec<u8>>> = RefCell::new(RbTree::new());
     pub static TIMER_IDS: RefCell<Vec<TimerId>> = RefCell::new(Vec::new());
 }
 
+// Certifies the tip of the chain with the ICRC-3 labels:
+// `last_block_index` as LEB128 encoded nat and `last_block_hash` as blob.
+pub fn set_certified_tip(last_block_index: u128, last_block_hash: Hash) {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.insert("last_block_index", leb128_encode(last_block_index));
+        tree.insert("last_block_hash", last_block_hash.to_vec());
+        ic_cdk::api::set_certified_data(&tree.root_hash());
+    });
+}
+
+// Returns the CBOR encoded hash tree revealing both tip labels.
+fn tip_witness() -> Vec<u8> {
+    TREE.with(|tree| {
+        let tree = tree.borrow();
+        let mut witness = vec![];
+        let mut witness_serializer = serde_cbor::Serializer::new(&mut witness);
+        witness_serializer.self_describe().unwrap();
+        tree.as_hash_tree()
+            .serialize(&mut witness_serializer)
+            .unwrap();
+        witness
+    })
+}
+
 pub async fn call_sync_logs(
     archive_log_canister: Principal,
     txn_logs: Vec<Transaction>,
@@ -1793,21 +2226,42 @@ pub async fn call_sync_logs(
     }
 }
 
-async fn call_append_blocks(archive_log_canister: Principal, blocks: Vec<Block>) -> SyncReceipt {
-    // sync logs
-    ic_cdk::println!("call_append: {:?}", blocks);
+async fn call_remaining_capacity(archive_canister: Principal) -> Result<RemainingCapacity, String> {
+    let call_result: Result<(RemainingCapacity,), _> =
+        ic_cdk::api::call::call(archive_canister, "remaining_capacity", ()).await;
+    call_result
+        .map(|(remaining,)| remaining)
+        .map_err(|(code, msg)| format!("Rejection Code: {:?}, Message: {:?}", code, msg))
+}
 
+// Appends `blocks` starting at the global id `first_index` and succeeds only
+// if the archive reports the last appended block as its new tip.
+async fn call_append_blocks(
+    archive_log_canister: Principal,
+    blocks: Vec<Block>,
+    first_index: u128,
+) -> SyncReceipt {
+    if blocks.is_empty() {
+        return Ok(0);
+    }
     ic_cdk::println!(
         "append_blocks archive_log_canister: {:?}",
         archive_log_canister.to_text()
     );
-    let call_result: Result<(), _> =
+    let expected_tip = blocks.last().map(|block| ArchiveTip {
+        last_block_index: first_index + blocks.len() as u128 - 1,
+        last_block_hash: hash_icrc_value(block.as_ref()),
+    });
+    let call_result: Result<(Result<Option<ArchiveTip>, AppendBlocksError>,), _> =
         ic_cdk::api::call::call(archive_log_canister, "append_blocks", (blocks.clone(),)).await;
 
-    // ic_cdk::println!("call_append_blocks call_result: {:?}", call_result);
-
     match call_result {
-        Ok(_) => Ok(blocks.len() as u32),
+        Ok((Ok(tip),)) if tip == expected_tip => Ok(blocks.len() as u32),
+        Ok((Ok(tip),)) => Err(InsertTransactionError::Unexpected(format!(
+            "archive tip {:?} does not match the appended blocks",
+            tip
+        ))),
+        Ok((Err(err),)) => Err(InsertTransactionError::Unexpected(format!("{:?}", err))),
         Err((_rejection_code, _msg)) => Err(InsertTransactionError::RemoteError),
     }
 }
@@ -1829,6 +2283,7 @@ async fn clean_local_ledger_task() {
     let txn_ledger_size = STATE.with(|s| s.borrow().txn_ledger.len());
     let setting = STATE.with(|s| s.borrow().archive_ledger_info.setting.clone());
     let local_first_index = STATE.with(|s| s.borrow().archive_ledger_info.first_index);
+    let archived_hash = STATE.with(|s| s.borrow().archive_ledger_info.archived_hash);
     let max_active_records = setting.max_active_records;
     let max_records_in_archive_instance = setting.max_records_in_archive_instance;
     let max_records_to_archive = setting.max_records_to_archive;
@@ -1836,12 +2291,14 @@ async fn clean_local_ledger_task() {
     let archive_cycles = setting.archive_cycles;
     let archive_controllers = setting.archive_controllers;
     let max_archive_pages = setting.max_archive_pages;
+    let archive_index_type = setting.archive_index_type;
+    let supported_blocks = STATE.with(|s| s.borrow().archive_ledger_info.supported_blocks.clone());
 
     let local_cycles = ic_cdk::api::canister_balance128();
 
     let mut is_recall_at_end = false;
 
-    let archive_count = STATE.with(|s| s.borrow().archive_ledger_info.archives.len());
+    let archive_count = STATE.with(|s| s.borrow().archives.len());
 
     if txn_ledger_size < max_active_records as u64 {
         ic_cdk::println!("clean_local_ledger_task: txn_ledger_size < max_active_records, don't clean if not necessary");
@@ -1862,10 +2319,13 @@ async fn clean_local_ledger_task() {
     if archive_count == 0 {
         ic_cdk::println!("clean_local_ledger_task: create a new archive canister");
         let create_args: ArchiveCreateArgs = ArchiveCreateArgs {
+            index_type: archive_index_type.clone(),
             max_pages: max_archive_pages,
-            max_records: max_active_records,
-            first_index: 0,
+            max_records: max_records_in_archive_instance,
+            first_index: local_first_index,
             controllers: archive_controllers,
+            supported_blocks: supported_blocks.clone(),
+            parent_hash: archived_hash,
         };
         // ic_cdk::println!("local_cycles: {}", local_cycles);
         // ic_cdk::println!("archive_cycles: {}", archive_cycles);
@@ -1876,7 +2336,7 @@ async fn clean_local_ledger_task() {
             match archive_canister {
                 Ok(canister_id) => {
                     let range = TransactionRange {
-                        start: 0,
+                        start: local_first_index,
                         length: 0,
                     };
                     STATE.with(|s: &RefCell<State>| {
@@ -1900,26 +2360,34 @@ async fn clean_local_ledger_task() {
             return;
         }
     } else {
-        let current_last_archive = STATE.with(|s| {
-            s.borrow()
-                .archive_ledger_info
-                .archives
-                .clone()
-                .into_iter()
-                .last()
-        });
+        let current_last_archive = STATE.with(|s| s.borrow().sorted_archives().pop());
 
         if let Some(current_last_archive) = current_last_archive {
-            if current_last_archive.1.length >= max_records_in_archive_instance {
+            // Roll over before the next batch no longer fits into the records or
+            // the stable memory budget of the current archive.
+            let remaining = call_remaining_capacity(current_last_archive.0).await.ok();
+            let batch_size = (txn_ledger_size as u128)
+                .saturating_sub(settle_to_records)
+                .min(max_records_to_archive);
+            let batch_bytes = STATE.with(|s| s.borrow().archive_batch_bytes(batch_size as usize));
+            let is_full = current_last_archive.1.length >= max_records_in_archive_instance
+                || remaining
+                    .as_ref()
+                    .is_some_and(|r| r.records == 0 || r.bytes < batch_bytes);
+
+            if is_full {
                 ic_cdk::println!(
                     "clean_local_ledger_task: old archive is full, create a new archive canister"
                 );
 
                 let create_args: ArchiveCreateArgs = ArchiveCreateArgs {
+                    index_type: archive_index_type.clone(),
                     max_pages: max_archive_pages,
-                    max_records: max_active_records,
-                    first_index: current_last_archive.1.start + current_last_archive.1.length,
+                    max_records: max_records_in_archive_instance,
+                    first_index: local_first_index,
                     controllers: archive_controllers,
+                    supported_blocks: supported_blocks.clone(),
+                    parent_hash: archived_hash,
                 };
 
                 if local_cycles > (archive_cycles * 2) {
@@ -1955,6 +2423,9 @@ async fn clean_local_ledger_task() {
             } else {
                 last_archive = Some(current_last_archive.clone());
                 capacity = max_records_in_archive_instance - current_last_archive.1.length;
+                if let Some(remaining) = remaining {
+                    capacity = capacity.min(remaining.records as u128);
+                }
             }
         }
     }
@@ -1985,35 +2456,36 @@ async fn clean_local_ledger_task() {
             to_archive_ids.push(key_id.clone());
         }
         let to_archive_amount = to_archive_vec.len() as u128;
+        let last_archived_hash = to_archive_vec
+            .last()
+            .map(|block| hash_icrc_value(block.as_ref()));
 
         ic_cdk::println!(
             "clean_local_ledger_task: to_archive size {}",
             to_archive_amount
         );
 
-        let call_result = call_append_blocks(last_archive.0, to_archive_vec).await;
+        let call_result =
+            call_append_blocks(last_archive.0, to_archive_vec, local_first_index).await;
 
         match call_result {
             Ok(_count) => {
                 STATE.with(|s| s.borrow_mut().remove_txn_logs(&to_archive_ids));
-                STATE.with(|s| s.borrow_mut().archive_ledger_info.first_index += to_archive_amount);
                 STATE.with(|s| {
-                    if let Some(transaction_range) = s
-                        .borrow_mut()
-                        .archive_ledger_info
-                        .archives
-                        .get_mut(&last_archive.0)
-                    {
-                        transaction_range.length += to_archive_amount;
-                        transaction_range.start = transaction_range.start;
-                    }
+                    let mut s = s.borrow_mut();
+                    s.archive_ledger_info.first_index += to_archive_amount;
+                    s.archive_ledger_info.archived_hash = last_archived_hash;
+                });
+                STATE.with(|s| {
+                    s.borrow_mut()
+                        .extend_archive(last_archive.0, to_archive_amount)
                 });
             }
-            Err(_) => {
+            Err(err) => {
                 STATE.with(|s: &RefCell<State>| {
                     s.borrow_mut().archive_ledger_info.is_cleaning = false
                 });
-                ic_cdk::println!("clean_local_ledger_task: to_archive fail");
+                ic_cdk::println!("clean_local_ledger_task: to_archive fail {:?}", err);
             }
         }
     }
@@ -2024,3 +2496,318 @@ async fn clean_local_ledger_task() {
         set_clean_up_timer()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::default_account;
+
+    const TS: u64 = 1_700_000_000_000_000_000;
+
+    // Moves the oldest `count` local blocks to `canister_id` like `clean_local_ledger_task`.
+    fn archive_blocks(
+        state: &mut State,
+        archives: &mut BTreeMap<Principal, BTreeMap<u128, Value>>,
+        canister_id: Principal,
+        count: usize,
+    ) {
+        let first_index = state.archive_ledger_info.first_index;
+        let to_archive = state.get_archive_txn_ledger(count);
+        let archive = archives.entry(canister_id).or_default();
+        for (id, txn) in to_archive.iter() {
+            archive.insert(*id, txn.block.clone().unwrap().into_inner());
+        }
+        let ids: Vec<u128> = to_archive.keys().cloned().collect();
+        state.remove_txn_logs(&ids);
+        state.archive_ledger_info.first_index += ids.len() as u128;
+        if state.archives.get(&canister_id).is_none() {
+            state.add_archive(
+                canister_id,
+                TransactionRange {
+                    start: first_index,
+                    length: 0,
+                },
+            );
+        }
+        state.extend_archive(canister_id, ids.len() as u128);
+    }
+
+    #[test]
+    fn icrc3_get_blocks_routes_every_block_id() {
+        let mut state = State::default();
+        let mut expected = vec![];
+        let mut phash = None;
+        for tid in 0..10u128 {
+            let to = default_account(&Principal::from_slice(&[tid as u8 + 1; 10]));
+            let txn_id = state.get_txn_id();
+            let mut txn = Transaction::mint(TS, tid, None, to, BTreeMap::new(), None);
+            let block = Block::new(phash, txn.clone());
+            phash = Some(hash_icrc_value(block.as_ref()));
+            expected.push((txn_id, block.clone().into_inner()));
+            txn.block = Some(block);
+            state.txn_ledger.insert(txn_id, txn);
+        }
+
+        // the later archive sorts first by principal
+        let mut archives = BTreeMap::new();
+        let first_archive = Principal::from_slice(&[9; 10]);
+        let second_archive = Principal::from_slice(&[1; 10]);
+        archive_blocks(&mut state, &mut archives, first_archive, 3);
+        archive_blocks(&mut state, &mut archives, second_archive, 4);
+
+        for args in [
+            vec![TransactionRange {
+                start: 0,
+                length: 100,
+            }],
+            (0..10)
+                .map(|start| TransactionRange { start, length: 1 })
+                .collect(),
+        ] {
+            let result = state.icrc3_get_blocks(args);
+            assert_eq!(result.log_length, 10);
+
+            let mut blocks: Vec<(u128, Value)> = result
+                .blocks
+                .into_iter()
+                .map(|block| (block.id, block.block))
+                .collect();
+            for archived in result.archived_blocks {
+                assert_eq!(archived.callback.method, "icrc3_get_blocks");
+                let archive = &archives[&archived.callback.canister_id];
+                for range in archived.args {
+                    for id in range.start..range.start + range.length {
+                        blocks.push((id, archive[&id].clone()));
+                    }
+                }
+            }
+            blocks.sort_by_key(|(id, _)| *id);
+            assert_eq!(blocks, expected);
+        }
+    }
+
+    #[test]
+    fn upgrades_restore_config_and_stable_data() {
+        let archive = Principal::from_slice(&[9; 10]);
+        STATE.with(|s| {
+            let mut s = s.borrow_mut();
+            s.icrc7_name = "Upgraded".into();
+            s.add_archive(
+                archive,
+                TransactionRange {
+                    start: 0,
+                    length: 3,
+                },
+            );
+            s.set_sync_pending_txn_ids(Some(vec![4, 5]));
+        });
+
+        save_state();
+        restore_state();
+        STATE.with(|s| {
+            let mut s = s.borrow_mut();
+            s.apply_upgrade_arg(UpgradeArg {
+                icrc7_symbol: Some("NEW".into()),
+                icrc7_max_memo_size: Some(64),
+                icrc7_asset_base_url: Some("https://assets.example".into()),
+                approval_settings: Some(crate::icrc37_types::InitApprovalsArg {
+                    max_approvals: Some(5),
+                    max_approvals_per_token_or_collection: None,
+                    max_revoke_approvals: None,
+                    settle_to_approvals: None,
+                    collection_approval_requires_token: None,
+                }),
+                ..UpgradeArg::default()
+            });
+            assert_eq!(s.icrc7_name(), "Upgraded");
+            assert_eq!(s.icrc7_symbol(), "NEW");
+            assert_eq!(s.icrc7_max_memo_size, Some(64));
+            assert_eq!(
+                s.icrc7_asset_base_url(),
+                Some("https://assets.example".into())
+            );
+            assert_eq!(s.approval_ledger_info.max_approvals, 5);
+            assert_eq!(s.approval_ledger_info.max_revoke_approvals, 10000);
+            assert_eq!(s.sorted_archives()[0].1.length, 3);
+            assert_eq!(s.get_sync_pending_txn_ids(), Some(vec![4, 5]));
+        });
+    }
+
+    #[test]
+    fn upgraded_configs_are_validated() {
+        let mut state = State::default();
+        assert_eq!(state.validate_config(false), Ok(()));
+        state.apply_upgrade_arg(UpgradeArg {
+            icrc7_max_take_value: Some(10),
+            icrc7_default_take_value: Some(20),
+            icrc7_max_update_batch_size: Some(0),
+            icrc7_max_memo_size: Some(0),
+            ..UpgradeArg::default()
+        });
+        let fields: Vec<String> = state
+            .validate_config(false)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.field)
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                "icrc7_max_update_batch_size",
+                "icrc7_default_take_value",
+                "icrc7_max_memo_size"
+            ]
+        );
+
+        // approval limits changed by an upgrade must be consistent with the current ones
+        let mut state = State::default();
+        state.apply_upgrade_arg(UpgradeArg {
+            approval_settings: Some(InitApprovalsArg {
+                max_approvals: Some(100),
+                max_approvals_per_token_or_collection: None,
+                max_revoke_approvals: None,
+                settle_to_approvals: Some(90),
+                collection_approval_requires_token: None,
+            }),
+            ..UpgradeArg::default()
+        });
+        assert_eq!(state.validate_config(true), Ok(()));
+        state.apply_upgrade_arg(UpgradeArg {
+            approval_settings: Some(InitApprovalsArg {
+                max_approvals: Some(0),
+                max_approvals_per_token_or_collection: None,
+                max_revoke_approvals: None,
+                settle_to_approvals: None,
+                collection_approval_requires_token: None,
+            }),
+            ..UpgradeArg::default()
+        });
+        assert!(state.validate_config(true).is_err());
+    }
+
+    #[test]
+    fn legacy_state_is_migrated() {
+        use ciborium::value::Value as Cbor;
+        use ic_stable_structures::writer::Writer;
+
+        let archive = Principal::from_slice(&[9; 10]);
+        let state = State {
+            icrc7_name: "Legacy".into(),
+            ..State::default()
+        };
+        // principals are only written as bytes by the CBOR writer
+        fn to_cbor<T: Serialize>(value: &T) -> Cbor {
+            let mut bytes = vec![];
+            ciborium::ser::into_writer(value, &mut bytes).unwrap();
+            ciborium::de::from_reader(bytes.as_slice()).unwrap()
+        }
+
+        // the heap layout before the versioned config
+        let mut legacy = to_cbor(&StateV1::from(&state));
+        let fields = legacy.as_map_mut().unwrap();
+        fields.push((
+            Cbor::Text("sync_pending_txn_ids".into()),
+            to_cbor(&Some(vec![7u128])),
+        ));
+        for (key, value) in fields.iter_mut() {
+            if key.as_text() == Some("archive_ledger_info") {
+                let archives = BTreeMap::from([(
+                    archive,
+                    TransactionRange {
+                        start: 0,
+                        length: 5,
+                    },
+                )]);
+                value
+                    .as_map_mut()
+                    .unwrap()
+                    .push((Cbor::Text("archives".into()), to_cbor(&archives)));
+            }
+        }
+        let mut state_bytes = vec![];
+        ciborium::ser::into_writer(&legacy, &mut state_bytes).unwrap();
+        let mut memory = get_upgrades_memory();
+        let mut writer = Writer::new(&mut memory, 0);
+        writer
+            .write(&(state_bytes.len() as u32).to_le_bytes())
+            .unwrap();
+        writer.write(&state_bytes).unwrap();
+
+        restore_state();
+        STATE.with(|s| {
+            let s = s.borrow();
+            assert_eq!(s.icrc7_name(), "Legacy");
+            assert_eq!(s.sorted_archives()[0].0, archive);
+            assert_eq!(s.sorted_archives()[0].1.length, 5);
+            assert_eq!(s.get_sync_pending_txn_ids(), Some(vec![7]));
+        });
+    }
+
+    #[test]
+    fn handed_over_minting_authority_can_mint() {
+        let mut state = State::default();
+        let creator = Principal::from_slice(&[3; 29]);
+        // the launchpad hands minting over to the plain account of the creator
+        state.set_minting_authority(Account {
+            owner: creator,
+            subaccount: None,
+        });
+        let arg = MintArg {
+            from_subaccount: None,
+            to: default_account(&creator),
+            token_id: 1,
+            memo: None,
+            token_name: None,
+            token_description: None,
+            token_logo: None,
+        };
+        let caller = account_transformer(Account {
+            owner: creator,
+            subaccount: arg.from_subaccount,
+        });
+        assert!(state.mock_mint(&caller, &arg).is_ok());
+    }
+
+    #[test]
+    fn burns_count_each_token_once() {
+        let mut state = State::default();
+        let owner = Principal::from_slice(&[1; 29]);
+        for token_id in 1..=2 {
+            let token = Icrc7Token::new(
+                token_id,
+                format!("T{}", token_id),
+                None,
+                None,
+                default_account(&owner),
+            );
+            state.tokens.insert(token_id, token);
+        }
+        state.icrc7_total_supply = 2;
+        let arg = |token_id| BurnArg {
+            from_subaccount: None,
+            token_id,
+            memo: None,
+        };
+
+        let results = state.check_burns(&owner, &[arg(1), arg(2), arg(1)]);
+        assert!(results[0].is_ok() && results[1].is_ok());
+        assert!(matches!(
+            results[2],
+            Err(BurnError::GenericError { error_code: 11, .. })
+        ));
+        let stranger = Principal::from_slice(&[2; 29]);
+        assert!(matches!(
+            state.check_burns(&stranger, &[arg(1)])[0],
+            Err(BurnError::Unauthorized)
+        ));
+
+        state.burn_mode = BurnMode::Tombstone;
+        assert_eq!(state.burn_token(1), None);
+        assert_eq!((state.icrc7_total_supply, state.burned_supply), (1, 1));
+        assert!(state.get_token(&1).is_none());
+        assert!(matches!(
+            state.check_burns(&owner, &[arg(1), arg(2)])[0],
+            Err(BurnError::NonExistingTokenId)
+        ));
+    }
+}
@@ -2,7 +2,9 @@ use candid::Principal;
 use ic_cdk_macros::update;
 
 use crate::{
-    guards::owner_guard, state::STATE, BurnArg, BurnResult, MintArg, MintResult, TransferArg,
+    guards::{owner_guard, owner_or_controller_guard},
+    state::STATE,
+    AdminBurnArg, AdminTransferArg, BurnArg, BurnResult, MintArg, MintResult, TransferArg,
     TransferResult,
 };
 use icrc_ledger_types::icrc1::account::Account;
@@ -31,8 +33,24 @@ pub fn burn(args: Vec<BurnArg>) -> Vec<Option<BurnResult>> {
     STATE.with(|s| s.borrow_mut().burn(&caller, args))
 }
 
+// Burns any token regardless of its owner, e.g. for legal takedowns.
+// Requires the collection to be initialized with `allow_admin_actions`.
 #[update(guard = "owner_guard")]
+pub fn admin_burn(arg: AdminBurnArg) -> BurnResult {
+    let caller = ic_cdk::caller();
+    STATE.with(|s| s.borrow_mut().admin_burn(&caller, arg))
+}
+
+// Moves any token to a new owner, e.g. to recover tokens of custodial users who lost their keys.
+// Requires the collection to be initialized with `allow_admin_actions`.
+#[update(guard = "owner_guard")]
+pub fn admin_transfer(arg: AdminTransferArg) -> TransferResult {
+    let caller = ic_cdk::caller();
+    STATE.with(|s| s.borrow_mut().admin_transfer(&caller, arg))
+}
+
+#[update(guard = "owner_or_controller_guard")]
 pub fn set_minting_authority(minting_account: Account) -> bool {
-    STATE.with(|s| s.borrow_mut().minting_authority = Some(minting_account));
+    STATE.with(|s| s.borrow_mut().set_minting_authority(minting_account));
     return true;
 }
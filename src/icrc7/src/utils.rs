@@ -34,6 +34,233 @@ pub fn burn_account() -> Account {
     }
 }
 
+// Whether `account` is the burn account of this ledger, tokens only get there by a burn.
+pub fn is_burn_account(account: &Account) -> bool {
+    account.subaccount == Some(burn_subaccount()) && account.owner == ic_cdk::api::id()
+}
+
 pub fn hash_icrc_value(value: &Value) -> generic_value::Hash {
     return value.hash();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{icrc3_types::Block, icrc7_types::Transaction};
+    use std::collections::BTreeMap;
+
+    const TS: u64 = 1_700_000_000_000_000_000;
+
+    fn account(byte: u8) -> Account {
+        default_account(&Principal::from_slice(&[byte; 10]))
+    }
+
+    fn tx_keys(block: &Block) -> Vec<String> {
+        match block.value() {
+            Value::Map(map) => match map.get("tx") {
+                Some(Value::Map(tx)) => tx.keys().cloned().collect(),
+                _ => panic!("block has no tx map"),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn block_keys(block: &Block) -> Vec<String> {
+        block.clone().into_map().keys().cloned().collect()
+    }
+
+    #[test]
+    fn hash_icrc_value_test_vectors() {
+        let test_vectors = vec![
+            (
+                Value::Nat(42_u32.into()),
+                "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1",
+            ),
+            (
+                Value::Int((-42).into()),
+                "de5a6f78116eca62d7fc5ce159d23ae6b889b365a1739ad2cf36f925a140d0cc",
+            ),
+            (
+                Value::text("Hello, World!"),
+                "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f",
+            ),
+            (
+                Value::blob(hex::decode("01020304").unwrap()),
+                "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a",
+            ),
+            (
+                Value::Array(vec![
+                    Value::Nat(3_u32.into()),
+                    Value::text("foo"),
+                    Value::blob(hex::decode("0506").unwrap()),
+                ]),
+                "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6",
+            ),
+            (
+                Value::map(vec![
+                    (
+                        "from",
+                        Value::blob(
+                            hex::decode("00abcdef0012340056789a00bcdef000012345678900abcdef01")
+                                .unwrap(),
+                        ),
+                    ),
+                    (
+                        "to",
+                        Value::blob(
+                            hex::decode("00ab0def0012340056789a00bcdef000012345678900abcdef01")
+                                .unwrap(),
+                        ),
+                    ),
+                    ("amount", Value::Nat(42_u32.into())),
+                    ("created_at", Value::Nat(1699218263_u32.into())),
+                    ("memo", Value::Nat(0_u32.into())),
+                ]),
+                "c56ece650e1de4269c5bdeff7875949e3e2033f85b2d193c2ff4f7f78bdcfc75",
+            ),
+        ];
+
+        for (input, expected) in test_vectors {
+            assert_eq!(hex::encode(hash_icrc_value(&input)), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn block_chain_test_vectors() {
+        let mut meta = BTreeMap::new();
+        meta.insert("Name".to_string(), Value::text("ICRC7 1"));
+        let mint = Block::new(
+            None,
+            Transaction::mint(TS, 1, Some(account(1)), account(2), meta, None),
+        );
+        assert_eq!(block_keys(&mint), vec!["btype", "ts", "tx"]);
+        assert_eq!(tx_keys(&mint), vec!["from", "meta", "tid", "to"]);
+        let mint_hash = hash_icrc_value(&mint);
+        assert_eq!(
+            hex::encode(mint_hash),
+            "f03e05f9e0476019fd92d5cd385e7456a8255f50c9706473360284f7cb7f1992"
+        );
+
+        let mut transfer =
+            Transaction::transfer(TS + 1, 1, account(2), account(3), Some(b"memo".to_vec()));
+        transfer.created_at_time = Some(TS - 5);
+        let transfer = Block::new(Some(mint_hash), transfer);
+        assert_eq!(block_keys(&transfer), vec!["btype", "phash", "ts", "tx"]);
+        assert_eq!(tx_keys(&transfer), vec!["from", "memo", "tid", "to", "ts"]);
+        let transfer_hash = hash_icrc_value(&transfer);
+        assert_eq!(
+            hex::encode(transfer_hash),
+            "b708fa111cd81f52f0e51ed77b4cc687bfec5d058a452fa0ff68fa2f4ab4ef36"
+        );
+
+        let approve = Block::new(
+            Some(transfer_hash),
+            Transaction::approve_collection(TS + 2, account(3), account(2), Some(TS + 1000), None),
+        );
+        assert_eq!(tx_keys(&approve), vec!["exp", "from", "spender"]);
+        let approve_hash = hash_icrc_value(&approve);
+        assert_eq!(
+            hex::encode(approve_hash),
+            "9377719214afc7b1aa58cbc32c1a40d1809cb2f28de9ff1a2a7ef985cfc5a03e"
+        );
+
+        let revoke = Block::new(
+            Some(approve_hash),
+            Transaction::revoke_collection(TS + 3, account(3), Some(account(2)), None),
+        );
+        assert_eq!(tx_keys(&revoke), vec!["from", "spender"]);
+        let revoke_hash = hash_icrc_value(&revoke);
+        assert_eq!(
+            hex::encode(revoke_hash),
+            "9011d862e4a0bcd0b1bb012c7dae110c291da5e19af1b4c11d912b69fc970767"
+        );
+
+        let mut meta = BTreeMap::new();
+        meta.insert("Name".to_string(), Value::text("ICRC7 1 v2"));
+        let update = Block::new(
+            Some(revoke_hash),
+            Transaction::update(TS + 4, 1, account(3), meta, None),
+        );
+        assert_eq!(tx_keys(&update), vec!["from", "meta", "tid"]);
+        let update_hash = hash_icrc_value(&update);
+        assert_eq!(
+            hex::encode(update_hash),
+            "201bb6370545e94e1405e0c2c393996f73b29e2dfaf51751b26a09b7f4675d0e"
+        );
+
+        let revoke_token = Block::new(
+            Some(update_hash),
+            Transaction::revoke(TS + 5, 1, account(3), Some(account(2)), None),
+        );
+        assert_eq!(tx_keys(&revoke_token), vec!["from", "spender", "tid"]);
+        let revoke_token_hash = hash_icrc_value(&revoke_token);
+        assert_eq!(
+            hex::encode(revoke_token_hash),
+            "f795c455cea534d54246ef59efd9b9c055d245ea6abb6e7e5e76d730d39b266c"
+        );
+
+        let transfer_from = Block::new(
+            Some(revoke_token_hash),
+            Transaction::transfer_from(TS + 6, 1, account(3), account(4), account(2), None),
+        );
+        assert_eq!(
+            tx_keys(&transfer_from),
+            vec!["from", "spender", "tid", "to"]
+        );
+        let transfer_from_hash = hash_icrc_value(&transfer_from);
+        assert_eq!(
+            hex::encode(transfer_from_hash),
+            "cc22e36762043893ba46196460322ad6f4626ffb61c348ef8d8d2fbb4d0d9e4f"
+        );
+
+        let admin_burn = Block::new(
+            Some(transfer_from_hash),
+            Transaction::admin_burn(
+                TS + 7,
+                1,
+                account(4),
+                Some(account(9)),
+                account(1),
+                Some(b"takedown".to_vec()),
+            ),
+        );
+        assert_eq!(
+            tx_keys(&admin_burn),
+            vec!["authority", "from", "memo", "tid"]
+        );
+        let admin_burn_hash = hash_icrc_value(&admin_burn);
+        assert_eq!(
+            hex::encode(admin_burn_hash),
+            "02a5de9f1f8abea793c3dd5470e962984a2871ebd1c625044bcebfac1ee6c794"
+        );
+
+        let admin_transfer = Block::new(
+            Some(admin_burn_hash),
+            Transaction::admin_transfer(
+                TS + 8,
+                2,
+                account(5),
+                account(6),
+                account(1),
+                Some(b"recovery".to_vec()),
+            ),
+        );
+        assert_eq!(
+            tx_keys(&admin_transfer),
+            vec!["authority", "from", "memo", "tid", "to"]
+        );
+        assert_eq!(
+            hex::encode(hash_icrc_value(&admin_transfer)),
+            "b4e968d8802c89bbdc075d9c334a5b909e9e3b4d2ba4d873bf9fca73f2a25160"
+        );
+    }
+
+    #[test]
+    fn burn_block_omits_burn_address() {
+        let burn = Block::new(
+            None,
+            Transaction::burn(TS, 7, account(2), Some(account(9)), None),
+        );
+        assert_eq!(tx_keys(&burn), vec!["from", "tid"]);
+    }
+}
@@ -3,7 +3,7 @@ use crate::types::*;
 use candid::export_service;
 use candid::Principal;
 use ic_cdk_macros::query;
-use icrc_ledger_types::icrc3::blocks::GetBlocksRequest;
+use icrc_ledger_types::icrc3::blocks::{DataCertificate, GetBlocksRequest};
 
 #[query(name = "__get_candid_interface_tmp_hack")]
 fn export_candid() -> String {
@@ -1,11 +1,11 @@
 use candid::candid_method;
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade};
-use std::mem;
-
-use ic_cdk::storage;
 
 use crate::{
-    state::{StableState, State, DEFAULT_MAX_TRANSACTIONS_PER_GET_TRANSACTION_RESPONSE, STATE},
+    state::{
+        restore_state, save_state, set_certified_tip, State,
+        DEFAULT_MAX_TRANSACTIONS_PER_GET_TRANSACTION_RESPONSE, STATE,
+    },
     types::ArchiveInitArgs,
 };
 
@@ -20,8 +20,11 @@ pub fn init(arg: ArchiveInitArgs) {
             max_records: arg.max_records,
             block_index_offset: arg.first_index,
             block_index: arg.first_index,
+            last_hash: None,
+            parent_hash: arg.parent_hash,
             max_transactions_per_response: DEFAULT_MAX_TRANSACTIONS_PER_GET_TRANSACTION_RESPONSE,
             index_type: arg.index_type,
+            supported_blocks: arg.supported_blocks,
             ledger_id,
         };
         *s = state;
@@ -30,12 +33,14 @@ pub fn init(arg: ArchiveInitArgs) {
 
 #[pre_upgrade]
 fn pre_upgrade() {
-    let state = STATE.with(|state| mem::take(&mut *state.borrow_mut()));
-    let stable_state = StableState { state };
-    storage::stable_save((stable_state,)).unwrap();
+    save_state();
 }
 #[post_upgrade]
 fn post_upgrade() {
-    let (StableState { state },) = storage::stable_restore().unwrap();
-    STATE.with(|state0| *state0.borrow_mut() = state);
+    restore_state();
+
+    // certified data is not preserved across upgrades
+    if let Some(tip) = STATE.with(|s| s.borrow().tip()) {
+        set_certified_tip(&tip);
+    }
 }
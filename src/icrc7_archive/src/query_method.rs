@@ -2,11 +2,16 @@ use candid::{candid_method, Principal};
 use ic_cdk_macros::query;
 
 use crate::{
-    state::{with_archive_opts, with_blocks, STATE},
-    types::{Block, GetBlocksResult, QueryBlock},
+    state::{
+        block_memory_pages, remaining_bytes, tip_witness, with_archive_opts, with_blocks, STATE,
+    },
+    types::{
+        ArchiveStats, ArchiveTip, Block, BlockType, GetArchiveArgs, GetArchivesResultItem,
+        GetBlocksResult, QueryBlock, RemainingCapacity, Standard,
+    },
 };
-use icrc_ledger_types::icrc3::blocks::GetBlocksRequest;
-use num_traits::ToPrimitive;
+use icrc_ledger_types::icrc3::blocks::{DataCertificate, GetBlocksRequest};
+use serde_bytes::ByteBuf;
 
 #[query]
 #[candid_method(query)]
@@ -16,23 +21,90 @@ pub fn get_owner() -> Principal {
 
 #[query]
 #[candid_method(query)]
-fn remaining_capacity() -> u64 {
+pub fn get_tip() -> Option<ArchiveTip> {
+    STATE.with(|s| s.borrow().tip())
+}
+
+#[query]
+#[candid_method(query)]
+fn remaining_capacity() -> RemainingCapacity {
     let total_block_size = with_blocks(|blocks| blocks.len());
-    with_archive_opts(|opts| {
+    let records = with_archive_opts(|opts| {
         (opts.max_records as u64)
             .checked_sub(total_block_size)
             .expect("bug: archive capacity underflow")
+    });
+    RemainingCapacity {
+        records,
+        bytes: remaining_bytes(),
+    }
+}
+
+#[query]
+#[candid_method(query)]
+fn get_archive_stats() -> ArchiveStats {
+    with_archive_opts(|opts| ArchiveStats {
+        block_count: with_blocks(|blocks| blocks.len()),
+        first_index: opts.block_index_offset,
+        last_index: (opts.block_index > opts.block_index_offset).then(|| opts.block_index - 1),
+        stable_memory_pages: block_memory_pages(),
+        max_pages: opts.max_pages,
+        cycles: ic_cdk::api::canister_balance128(),
     })
 }
 
+// Returns the block types of the ledger this archive serves.
 #[query]
 #[candid_method(query)]
-fn get_transaction(index: u128) -> Option<Block> {
-    let idx_offset = with_archive_opts(|opts| opts.block_index_offset);
-    let relative_idx = (idx_offset <= index).then_some(index - idx_offset)?;
+pub fn icrc3_supported_block_types() -> Vec<BlockType> {
+    STATE.with(|s| s.borrow().supported_blocks.clone().unwrap_or_default())
+}
+
+// An archive holds only its own blocks, so it lists itself when it has any.
+#[query]
+#[candid_method(query)]
+pub fn icrc3_get_archives(arg: GetArchiveArgs) -> Vec<GetArchivesResultItem> {
+    let tip = STATE.with(|s| s.borrow().tip());
+    match (arg.from, tip) {
+        (None, Some(tip)) => vec![GetArchivesResultItem {
+            canister_id: ic_cdk::id(),
+            start: with_archive_opts(|opts| opts.block_index_offset),
+            end: tip.last_block_index,
+        }],
+        _ => vec![],
+    }
+}
+
+// The archive certifies the last block it stores
+#[query]
+#[candid_method(query)]
+pub fn icrc3_get_tip_certificate() -> Option<DataCertificate> {
+    STATE.with(|s| s.borrow().tip())?;
+    Some(DataCertificate {
+        certificate: ic_cdk::api::data_certificate().map(ByteBuf::from),
+        hash_tree: ByteBuf::from(tip_witness()),
+    })
+}
 
-    let block = with_blocks(|blocks| blocks.get(&relative_idx))?;
-    Some(block)
+#[query]
+#[candid_method(query)]
+pub fn icrc10_supported_standards() -> Vec<Standard> {
+    vec![
+        Standard {
+            name: "ICRC-3".into(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3".into(),
+        },
+        Standard {
+            name: "ICRC-10".into(),
+            url: "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-10".into(),
+        },
+    ]
+}
+
+#[query]
+#[candid_method(query)]
+fn get_transaction(index: u128) -> Option<Block> {
+    with_blocks(|blocks| blocks.get(&index))
 }
 
 #[query]
@@ -42,8 +114,6 @@ fn icrc3_get_blocks(reqs: Vec<GetBlocksRequest>) -> GetBlocksResult {
 
     let mut blocks = vec![];
     for req in reqs {
-        let mut id = req.start.0.to_u128().unwrap();
-
         let (start, length) = req
             .as_start_and_length()
             .unwrap_or_else(|msg| ic_cdk::api::trap(&msg));
@@ -52,42 +122,153 @@ fn icrc3_get_blocks(reqs: Vec<GetBlocksRequest>) -> GetBlocksResult {
             break;
         }
         let length = length.min(max_length);
-        let block_range = block_range(start, length);
-        for block in block_range {
+        for (id, block) in block_range(start, length) {
             blocks.push(QueryBlock {
-                id: id.clone(),
+                id,
                 block: block.value().clone(),
             });
-            id += 1u128;
         }
     }
 
     GetBlocksResult {
-        // We return the local log length because the archive
-        // knows only about its local blocks.
-        log_length: with_blocks(|blocks| blocks.len()) as u128,
+        // The archive holds the blocks up to `block_index`, which is
+        // the length of the whole log at the time they were archived.
+        log_length: with_archive_opts(|opts| opts.block_index),
         blocks,
         archived_blocks: vec![],
     }
 }
 
-fn block_range(start: u64, length: u64) -> Vec<Block> {
-    let offset = with_archive_opts(|opts| {
-        let block_index_offset = opts.block_index_offset as u64;
-        if start < block_index_offset {
-            ic_cdk::api::trap(&format!(
-                "requested index {} is less than the minimal index {} this archive serves",
-                start, block_index_offset
-            ));
-        }
-        start - block_index_offset
+// Returns the blocks with global ids in `start..start + length` held by this archive.
+pub fn block_range(start: u64, length: u64) -> Vec<(u128, Block)> {
+    let (first_index, next_index, max_length) = with_archive_opts(|opts| {
+        (
+            opts.block_index_offset,
+            opts.block_index,
+            opts.max_transactions_per_response,
+        )
     });
+    // the requested range ends where it would without the clamp below
+    let end = (start as u128 + length.min(max_length) as u128).min(next_index);
+    let start = (start as u128).max(first_index);
+    if start >= end {
+        return vec![];
+    }
+    with_blocks(|blocks| blocks.range(start..end))
+}
 
-    let length = length.min(with_archive_opts(|opts| opts.max_transactions_per_response));
-    with_blocks(|blocks| {
-        let limit = blocks.len().min(offset.saturating_add(length));
-        (offset..limit)
-            .map(|i| blocks.get(&(i as u128)).unwrap())
-            .collect()
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::update_method::store_blocks;
+    use icrc7_types::icrc3_types::{archived_blocks, TransactionRange};
+    use icrc_ledger_types::icrc::generic_value::Value;
+
+    #[test]
+    fn blocks_are_served_by_global_id() {
+        STATE.with(|s| {
+            let mut s = s.borrow_mut();
+            s.max_records = 10;
+            s.max_pages = 1000;
+            s.block_index_offset = 5;
+            s.block_index = 5;
+        });
+        let mut chain: Vec<Block> = vec![];
+        for tid in 5..8u64 {
+            let mut block = vec![("tx", Value::map(vec![("tid", Value::Nat64(tid))]))];
+            if let Some(parent) = chain.last() {
+                block.push(("phash", Value::blob(parent.hash().to_vec())));
+            }
+            chain.push(Block::from(Value::map(block)));
+        }
+        assert!(store_blocks(chain.clone()).is_ok());
+
+        let ids: Vec<u128> = block_range(0, 100).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![5, 6, 7]);
+        assert_eq!(block_range(6, 1), vec![(6, chain[1].clone())]);
+        assert!(block_range(8, 10).is_empty());
+        assert!(block_range(0, 3).is_empty());
+        assert_eq!(block_range(3, 3), vec![(5, chain[0].clone())]);
+        assert_eq!(get_transaction(7), Some(chain[2].clone()));
+        assert_eq!(get_transaction(4), None);
+    }
+
+    #[test]
+    fn ledger_routed_ranges_are_served() {
+        // the ledger hands blocks 0..3 to another archive and 3..7 to this one
+        let this_archive = Principal::from_slice(&[1; 29]);
+        let other_archive = Principal::from_slice(&[2; 29]);
+        let archives = vec![
+            (
+                other_archive,
+                TransactionRange {
+                    start: 0,
+                    length: 3,
+                },
+            ),
+            (
+                this_archive,
+                TransactionRange {
+                    start: 3,
+                    length: 4,
+                },
+            ),
+        ];
+
+        let parent = Block::from(Value::map(vec![("tid", Value::Nat64(2))]));
+        STATE.with(|s| {
+            let mut s = s.borrow_mut();
+            s.max_records = 10;
+            s.max_pages = 1000;
+            s.block_index_offset = 3;
+            s.block_index = 3;
+            s.parent_hash = Some(parent.hash());
+        });
+        let mut chain: Vec<Block> = vec![parent];
+        for tid in 3..7u64 {
+            let phash = chain.last().unwrap().hash();
+            chain.push(Block::from(Value::map(vec![
+                ("tid", Value::Nat64(tid)),
+                ("phash", Value::blob(phash.to_vec())),
+            ])));
+        }
+        assert!(store_blocks(chain[1..].to_vec()).is_ok());
+
+        let mut requests = vec![vec![TransactionRange {
+            start: 0,
+            length: 100,
+        }]];
+        requests.extend((0..10).map(|id| {
+            vec![TransactionRange {
+                start: id,
+                length: 1,
+            }]
+        }));
+        for args in requests {
+            let mut served = vec![];
+            for response in archived_blocks(&archives, &args, 10) {
+                if response.callback.canister_id != this_archive {
+                    continue;
+                }
+                assert_eq!(response.callback.method, "icrc3_get_blocks");
+                for range in response.args {
+                    let blocks = block_range(range.start as u64, range.length as u64);
+                    let ids: Vec<u128> = blocks.iter().map(|(id, _)| *id).collect();
+                    assert_eq!(
+                        ids,
+                        (range.start..range.start + range.length).collect::<Vec<_>>()
+                    );
+                    served.extend(blocks);
+                }
+            }
+            let expected: Vec<(u128, Block)> = (3..7u128)
+                .filter(|id| {
+                    args.iter()
+                        .any(|arg| arg.start <= *id && *id < arg.start + arg.length)
+                })
+                .map(|id| (id, chain[id as usize - 2].clone()))
+                .collect();
+            assert_eq!(served, expected, "{:?}", args);
+        }
+    }
 }
@@ -1,15 +1,25 @@
-use crate::types::{Block, IndexType};
-use candid::{CandidType, Decode, Encode, Principal};
+use crate::types::{ArchiveTip, Block, BlockType, IndexType};
+use candid::{CandidType, Decode, Encode, Int, Nat, Principal};
+use ic_certified_map::{AsHashTree, RbTree};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{storable::Bound, DefaultMemoryImpl, StableBTreeMap, Storable};
-use serde::Deserialize;
-use std::cell::RefCell;
+use ic_stable_structures::{
+    storable::Bound, DefaultMemoryImpl, Memory as _, StableBTreeMap, StableCell, StableLog,
+    Storable,
+};
+use icrc7_types::certification::leb128_encode;
+use icrc_ledger_types::icrc::generic_value::{Hash, Value};
+use num_bigint::{BigInt, BigUint};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::{cell::RefCell, collections::BTreeMap, ops::Range};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
 /// The maximum number of blocks to return in a single get_transactions request.
 pub const DEFAULT_MAX_TRANSACTIONS_PER_GET_TRANSACTION_RESPONSE: u64 = 2000;
 
+pub const WASM_PAGE_SIZE: u64 = 65536;
+
 // For a type to be used in a `StableBTreeMap`, it needs to implement the `Storable`
 // trait, which specifies how the type can be serialized/deserialized.
 //
@@ -32,7 +42,7 @@ impl Storable for Block {
     const BOUND: Bound = Bound::Unbounded;
 }
 
-#[derive(CandidType, Deserialize, Debug)]
+#[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct State {
     pub max_records: u128,
     pub max_pages: u128,
@@ -41,11 +51,33 @@ pub struct State {
     pub ledger_id: Principal,
     pub block_index_offset: u128,
     pub block_index: u128,
+    pub last_hash: Option<Hash>,
+    // the hash the block `block_index_offset` must link to
+    pub parent_hash: Option<Hash>,
+    pub supported_blocks: Option<Vec<BlockType>>,
 }
 
-#[derive(CandidType, Deserialize)]
-pub struct StableState {
-    pub state: State,
+impl State {
+    pub fn tip(&self) -> Option<ArchiveTip> {
+        self.last_hash.map(|last_block_hash| ArchiveTip {
+            last_block_index: self.block_index - 1,
+            last_block_hash,
+        })
+    }
+}
+
+// The configuration is kept in its own memory so that saving it on upgrade never
+// overwrites the blocks living in the other memories of the `MEMORY_MANAGER`.
+impl Storable for State {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 impl Default for State {
@@ -55,6 +87,9 @@ impl Default for State {
             max_pages: 0,
             block_index_offset: 0,
             block_index: 0,
+            last_hash: None,
+            parent_hash: None,
+            supported_blocks: None,
             max_transactions_per_response: DEFAULT_MAX_TRANSACTIONS_PER_GET_TRANSACTION_RESPONSE,
 
             ledger_id: Principal::anonymous(),
@@ -63,27 +98,545 @@ impl Default for State {
     }
 }
 
+const BLOCK_MAP_MEMORY_ID: MemoryId = MemoryId::new(0);
+const BLOCK_LOG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(1);
+const BLOCK_LOG_DATA_MEMORY_ID: MemoryId = MemoryId::new(2);
+const BLOCK_SNAPSHOT_MEMORY_ID: MemoryId = MemoryId::new(3);
+const STATE_MEMORY_ID: MemoryId = MemoryId::new(4);
+
+// CBOR representation of a block value. Big numbers are kept as little endian bytes
+// so that `Nat`, `Nat64` and `Int` values survive the round trip unchanged.
+#[derive(Serialize, Deserialize)]
+enum CborValue {
+    Blob(ByteBuf),
+    Text(String),
+    Nat(ByteBuf),
+    Nat64(u64),
+    Int(ByteBuf),
+    Array(Vec<CborValue>),
+    Map(BTreeMap<String, CborValue>),
+}
+
+impl From<&Value> for CborValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Blob(bytes) => CborValue::Blob(bytes.clone()),
+            Value::Text(text) => CborValue::Text(text.clone()),
+            Value::Nat(nat) => CborValue::Nat(ByteBuf::from(nat.0.to_bytes_le())),
+            Value::Nat64(n) => CborValue::Nat64(*n),
+            Value::Int(int) => CborValue::Int(ByteBuf::from(int.0.to_signed_bytes_le())),
+            Value::Array(values) => CborValue::Array(values.iter().map(Into::into).collect()),
+            Value::Map(map) => {
+                CborValue::Map(map.iter().map(|(k, v)| (k.clone(), v.into())).collect())
+            }
+        }
+    }
+}
+
+impl From<CborValue> for Value {
+    fn from(value: CborValue) -> Self {
+        match value {
+            CborValue::Blob(bytes) => Value::Blob(bytes),
+            CborValue::Text(text) => Value::Text(text),
+            CborValue::Nat(bytes) => Value::Nat(Nat(BigUint::from_bytes_le(&bytes))),
+            CborValue::Nat64(n) => Value::Nat64(n),
+            CborValue::Int(bytes) => Value::Int(Int(BigInt::from_signed_bytes_le(&bytes))),
+            CborValue::Array(values) => Value::Array(values.into_iter().map(Into::into).collect()),
+            CborValue::Map(map) => {
+                Value::Map(map.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+        }
+    }
+}
+
+pub fn encode_cbor_block(block: &Block) -> Vec<u8> {
+    let mut bytes = vec![];
+    ciborium::ser::into_writer(&CborValue::from(block.value()), &mut bytes)
+        .expect("bug: failed to encode a block");
+    bytes
+}
+
+pub fn decode_cbor_block(bytes: &[u8]) -> Block {
+    let value: CborValue = ciborium::de::from_reader(bytes).expect("bug: failed to decode a block");
+    Block::from(Value::from(value))
+}
+
+// The storage backend of the blocks, selected by `IndexType` at init.
+pub enum BlockStore {
+    // Typed blocks in a stable map keyed by their global block id.
+    Stable(StableBTreeMap<u128, Block, Memory>),
+    // CBOR encoded blocks in a stable log, entry `i` holds the block `first_index + i`.
+    StableTyped {
+        first_index: u128,
+        log: StableLog<Vec<u8>, Memory, Memory>,
+    },
+    // Blocks on the heap, snapshotted to stable memory on upgrade.
+    Managed {
+        blocks: BTreeMap<u128, Block>,
+        bytes: u64,
+    },
+}
+
+impl BlockStore {
+    pub fn init(index_type: &IndexType, first_index: u128) -> Self {
+        match index_type {
+            IndexType::Stable => {
+                BlockStore::Stable(StableBTreeMap::init(memory(BLOCK_MAP_MEMORY_ID)))
+            }
+            IndexType::StableTyped => BlockStore::StableTyped {
+                first_index,
+                log: StableLog::init(
+                    memory(BLOCK_LOG_INDEX_MEMORY_ID),
+                    memory(BLOCK_LOG_DATA_MEMORY_ID),
+                )
+                .expect("failed to init the block log"),
+            },
+            IndexType::Managed => {
+                let snapshot = snapshot_cell();
+                let blocks: Vec<(u128, Block)> = if snapshot.get().is_empty() {
+                    vec![]
+                } else {
+                    Decode!(snapshot.get(), Vec<(u128, Block)>).expect("failed to restore blocks")
+                };
+                BlockStore::Managed {
+                    bytes: blocks
+                        .iter()
+                        .map(|(_, block)| block.to_bytes().len() as u64)
+                        .sum(),
+                    blocks: blocks.into_iter().collect(),
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            BlockStore::Stable(map) => map.len(),
+            BlockStore::StableTyped { log, .. } => log.len(),
+            BlockStore::Managed { blocks, .. } => blocks.len() as u64,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: &u128) -> Option<Block> {
+        match self {
+            BlockStore::Stable(map) => map.get(id),
+            BlockStore::StableTyped { first_index, log } => {
+                let position = id.checked_sub(*first_index)?;
+                log.get(u64::try_from(position).ok()?)
+                    .map(|bytes| decode_cbor_block(&bytes))
+            }
+            BlockStore::Managed { blocks, .. } => blocks.get(id).cloned(),
+        }
+    }
+
+    pub fn range(&self, range: Range<u128>) -> Vec<(u128, Block)> {
+        match self {
+            BlockStore::Stable(map) => map.range(range).collect(),
+            BlockStore::StableTyped { .. } => range
+                .filter_map(|id| self.get(&id).map(|block| (id, block)))
+                .collect(),
+            BlockStore::Managed { blocks, .. } => blocks
+                .range(range)
+                .map(|(id, block)| (*id, block.clone()))
+                .collect(),
+        }
+    }
+
+    // Stores `block` under `id`, blocks are appended in the order of their ids.
+    pub fn insert(&mut self, id: u128, block: Block) {
+        match self {
+            BlockStore::Stable(map) => {
+                map.insert(id, block);
+            }
+            BlockStore::StableTyped { first_index, log } => {
+                assert_eq!(
+                    *first_index + log.len() as u128,
+                    id,
+                    "bug: blocks must be appended in order"
+                );
+                log.append(&encode_cbor_block(&block))
+                    .expect("failed to append to the block log");
+            }
+            BlockStore::Managed { blocks, bytes } => {
+                *bytes += block.to_bytes().len() as u64;
+                blocks.insert(id, block);
+            }
+        }
+    }
+
+    // Stable memory pages used by the blocks, the heap usage for `Managed`.
+    pub fn memory_pages(&self) -> u64 {
+        match self {
+            BlockStore::Stable(_) => memory(BLOCK_MAP_MEMORY_ID).size(),
+            BlockStore::StableTyped { .. } => {
+                memory(BLOCK_LOG_INDEX_MEMORY_ID).size() + memory(BLOCK_LOG_DATA_MEMORY_ID).size()
+            }
+            BlockStore::Managed { bytes, .. } => bytes.div_ceil(WASM_PAGE_SIZE),
+        }
+    }
+
+    // Saves heap blocks to stable memory, a no-op for the stable backends.
+    pub fn snapshot(&self) {
+        if let BlockStore::Managed { blocks, .. } = self {
+            let blocks: Vec<(&u128, &Block)> = blocks.iter().collect();
+            snapshot_cell()
+                .set(Encode!(&blocks).unwrap())
+                .expect("failed to snapshot blocks");
+        }
+    }
+}
+
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+fn snapshot_cell() -> StableCell<Vec<u8>, Memory> {
+    StableCell::init(memory(BLOCK_SNAPSHOT_MEMORY_ID), vec![])
+        .expect("failed to init the block snapshot")
+}
+
+fn state_cell() -> StableCell<State, Memory> {
+    StableCell::init(memory(STATE_MEMORY_ID), State::default())
+        .expect("failed to init the state memory")
+}
+
+/// Moves the configuration and the heap blocks to stable memory before an upgrade.
+pub fn save_state() {
+    with_blocks(|blocks| blocks.snapshot());
+    let state = STATE.with(|state| std::mem::take(&mut *state.borrow_mut()));
+    state_cell().set(state).expect("failed to save the state");
+}
+
+/// Reads the configuration back after an upgrade, the blocks are reopened on first use.
+pub fn restore_state() {
+    // must run before the memory manager reads its header
+    let state = migrate_legacy_layout(&DefaultMemoryImpl::default())
+        .unwrap_or_else(|| state_cell().get().clone());
+    STATE.with(|s| *s.borrow_mut() = state);
+}
+
+// The config of the releases before the state cell, saved by `stable_save` at the start of
+// the raw stable memory.
+#[derive(CandidType, Deserialize)]
+struct LegacyState {
+    max_records: u128,
+    max_pages: u128,
+    max_transactions_per_response: u64,
+    index_type: IndexType,
+    ledger_id: Principal,
+    block_index_offset: u128,
+    block_index: u128,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyStableState {
+    state: LegacyState,
+}
+
+// Layout of the memory manager of ic-stable-structures 0.6: a header of 2080 bytes, the
+// table of the memory owning each bucket and the buckets from the second page on.
+const MEMORY_MANAGER_HEADER_SIZE: usize = 2080;
+const MAX_NUM_BUCKETS: usize = 32768;
+const BUCKET_SIZE_IN_PAGES: u64 = 128;
+const UNALLOCATED_BUCKET: u8 = 255;
+
+// Migrates an archive saved by the releases before the state cell. Their `stable_save`
+// wrote the config over the memory manager header, which then no longer opens and would be
+// formatted again. The config is far smaller than the header, so the bucket table after it
+// still tells which buckets hold the block map: the header is rebuilt from it.
+fn migrate_legacy_layout<M: ic_stable_structures::Memory + Clone>(raw_memory: &M) -> Option<State> {
+    if raw_memory.size() == 0 {
+        return None;
+    }
+    let mut page = vec![0; WASM_PAGE_SIZE as usize];
+    raw_memory.read(0, &mut page);
+    if !page.starts_with(b"DIDL") {
+        return None;
+    }
+    let legacy = candid::de::IDLDeserialize::new(&page)
+        .and_then(|mut de| de.get_value::<LegacyStableState>())
+        .expect("failed to decode the config of a legacy archive")
+        .state;
+
+    let buckets = &page[MEMORY_MANAGER_HEADER_SIZE..MEMORY_MANAGER_HEADER_SIZE + MAX_NUM_BUCKETS];
+    // buckets are allocated in order, the first unallocated one ends the table
+    let allocated = buckets
+        .iter()
+        .take_while(|bucket| **bucket != UNALLOCATED_BUCKET)
+        .count();
+    if buckets[allocated..]
+        .iter()
+        .any(|bucket| *bucket != UNALLOCATED_BUCKET)
+    {
+        ic_cdk::trap("the block memory of this legacy archive is damaged, reinstall it");
+    }
+    let mut header = b"MGR\x01".to_vec();
+    header.extend((allocated as u16).to_le_bytes());
+    header.extend((BUCKET_SIZE_IN_PAGES as u16).to_le_bytes());
+    header.extend([0; 32]);
+    for id in 0..UNALLOCATED_BUCKET {
+        let owned = buckets[..allocated]
+            .iter()
+            .filter(|bucket| **bucket == id)
+            .count();
+        header.extend((owned as u64 * BUCKET_SIZE_IN_PAGES).to_le_bytes());
+    }
+    raw_memory.write(0, &header);
+
+    // legacy archives kept their blocks in the stable map whatever their index type
+    let mut blocks: StableBTreeMap<u128, Block, VirtualMemory<M>> =
+        StableBTreeMap::init(MemoryManager::init(raw_memory.clone()).get(BLOCK_MAP_MEMORY_ID));
+    // the old `append_blocks` stored block `id` under `id + 1`, in ascending order each key
+    // moves to the one freed before it
+    let legacy_ids: Vec<u128> = blocks.iter().map(|(id, _)| id).collect();
+    for id in legacy_ids {
+        if let Some(block) = blocks.remove(&id) {
+            blocks.insert(id - 1, block);
+        }
+    }
+    Some(State {
+        max_records: legacy.max_records,
+        max_pages: legacy.max_pages,
+        max_transactions_per_response: legacy.max_transactions_per_response,
+        index_type: IndexType::Stable,
+        ledger_id: legacy.ledger_id,
+        block_index_offset: legacy.block_index_offset,
+        block_index: legacy.block_index,
+        last_hash: blocks.last_key_value().map(|(_, block)| block.hash()),
+        parent_hash: None,
+        supported_blocks: None,
+    })
+}
+
 thread_local! {
     // The memory manager is used for simulating multiple memories. Given a `MemoryId` it can
     // return a memory that can be used by stable structures.
     pub static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 
-    pub static BLOCK_MAP: RefCell<StableBTreeMap<u128, Block, Memory>> = RefCell::new(
-        StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))),
-        )
-    );
+    // Opened on first use with the `index_type` of the archive.
+    pub static BLOCKS: RefCell<Option<BlockStore>> = const { RefCell::new(None) };
 
     pub static STATE: RefCell<State> = RefCell::default();
+    pub static TREE: RefCell<RbTree<&'static str, Vec<u8>>> = const { RefCell::new(RbTree::new()) };
 }
 
 /// A helper function to access the block list.
-pub fn with_blocks<R>(f: impl FnOnce(&StableBTreeMap<u128, Block, Memory>) -> R) -> R {
-    BLOCK_MAP.with(|cell| f(&cell.borrow()))
+pub fn with_blocks<R>(f: impl FnOnce(&BlockStore) -> R) -> R {
+    with_blocks_mut(|blocks| f(blocks))
+}
+
+/// A helper function to mutate the block list.
+pub fn with_blocks_mut<R>(f: impl FnOnce(&mut BlockStore) -> R) -> R {
+    BLOCKS.with(|cell| {
+        let mut blocks = cell.borrow_mut();
+        let blocks = blocks.get_or_insert_with(|| {
+            with_archive_opts(|opts| BlockStore::init(&opts.index_type, opts.block_index_offset))
+        });
+        f(blocks)
+    })
 }
 
 /// A helper function to access the configuration.
 pub fn with_archive_opts<R>(f: impl FnOnce(&State) -> R) -> R {
     STATE.with(|cell| f(&cell.borrow()))
 }
+
+// Puts the ICRC-3 tip labels into the tree and returns its new root hash:
+// `last_block_index` as LEB128 encoded nat and `last_block_hash` as blob.
+pub fn insert_tip_labels(tip: &ArchiveTip) -> Hash {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.insert("last_block_index", leb128_encode(tip.last_block_index));
+        tree.insert("last_block_hash", tip.last_block_hash.to_vec());
+        tree.root_hash()
+    })
+}
+
+// Certifies the tip of the archive.
+pub fn set_certified_tip(tip: &ArchiveTip) {
+    ic_cdk::api::set_certified_data(&insert_tip_labels(tip));
+}
+
+// Returns the CBOR encoded hash tree revealing both tip labels.
+pub fn tip_witness() -> Vec<u8> {
+    TREE.with(|tree| {
+        let tree = tree.borrow();
+        let mut witness = vec![];
+        let mut witness_serializer = serde_cbor::Serializer::new(&mut witness);
+        witness_serializer.self_describe().unwrap();
+        tree.as_hash_tree()
+            .serialize(&mut witness_serializer)
+            .unwrap();
+        witness
+    })
+}
+
+/// The number of stable memory pages used by the block list.
+pub fn block_memory_pages() -> u64 {
+    with_blocks(|blocks| blocks.memory_pages())
+}
+
+/// The number of bytes left in the `max_pages` budget of the archive.
+pub fn remaining_bytes() -> u64 {
+    let max_pages = with_archive_opts(|opts| opts.max_pages).min(u64::MAX as u128) as u64;
+    max_pages
+        .saturating_sub(block_memory_pages())
+        .saturating_mul(WASM_PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{query_method::block_range, update_method::store_blocks};
+
+    fn block(tid: u64) -> Block {
+        Block::from(Value::map(vec![
+            ("tid", Value::Nat64(tid)),
+            ("amount", Value::Nat(Nat::from(u128::MAX))),
+            ("delta", Value::Int(Int::from(-42))),
+            ("memo", Value::blob(vec![tid as u8])),
+            (
+                "tags",
+                Value::Array(vec![Value::text("a"), Value::Nat(Nat::from(1u8))]),
+            ),
+        ]))
+    }
+
+    #[test]
+    fn cbor_blocks_round_trip() {
+        let block = block(7);
+        let decoded = decode_cbor_block(&encode_cbor_block(&block));
+        assert_eq!(decoded, block);
+        assert_eq!(decoded.hash(), block.hash());
+    }
+
+    #[test]
+    fn block_stores_survive_reopening() {
+        for index_type in [
+            IndexType::Stable,
+            IndexType::StableTyped,
+            IndexType::Managed,
+        ] {
+            let mut store = BlockStore::init(&index_type, 5);
+            for id in 5..8 {
+                store.insert(id, block(id as u64));
+            }
+            store.snapshot();
+
+            // reopening the backend is what a canister upgrade does
+            let store = BlockStore::init(&index_type, 5);
+            assert_eq!(store.len(), 3, "{:?}", index_type);
+            assert_eq!(store.get(&6), Some(block(6)), "{:?}", index_type);
+            assert_eq!(store.get(&4), None, "{:?}", index_type);
+            assert_eq!(
+                store.range(6..10),
+                vec![(6, block(6)), (7, block(7))],
+                "{:?}",
+                index_type
+            );
+            assert!(store.memory_pages() > 0, "{:?}", index_type);
+        }
+    }
+
+    #[test]
+    fn legacy_layout_is_migrated() {
+        let raw_memory = DefaultMemoryImpl::default();
+        let mut blocks: StableBTreeMap<u128, Block, Memory> =
+            StableBTreeMap::init(MemoryManager::init(raw_memory.clone()).get(BLOCK_MAP_MEMORY_ID));
+        // the old `append_blocks` stored block `id` under `id + 1`
+        for id in 3..6 {
+            blocks.insert(id + 1, block(id as u64));
+        }
+
+        // the pre_upgrade of the old release saves its config over the memory manager header
+        let legacy = LegacyStableState {
+            state: LegacyState {
+                max_records: 10,
+                max_pages: 1000,
+                max_transactions_per_response: 2000,
+                index_type: IndexType::Managed,
+                ledger_id: Principal::anonymous(),
+                block_index_offset: 3,
+                block_index: 6,
+            },
+        };
+        raw_memory.write(0, &candid::encode_args((legacy,)).unwrap());
+
+        let state = migrate_legacy_layout(&raw_memory).unwrap();
+        assert!(matches!(state.index_type, IndexType::Stable));
+        assert_eq!((state.block_index_offset, state.block_index), (3, 6));
+        assert_eq!(state.last_hash, Some(block(5).hash()));
+        assert!(migrate_legacy_layout(&raw_memory).is_none());
+
+        let blocks: StableBTreeMap<u128, Block, Memory> =
+            StableBTreeMap::init(MemoryManager::init(raw_memory.clone()).get(BLOCK_MAP_MEMORY_ID));
+        assert_eq!(
+            blocks.range(..).collect::<Vec<_>>(),
+            (3..6).map(|id| (id, block(id as u64))).collect::<Vec<_>>()
+        );
+
+        // the migrated archive serves its first block and appends after its last one
+        MEMORY_MANAGER.with(|m| *m.borrow_mut() = MemoryManager::init(raw_memory));
+        BLOCKS.with(|b| *b.borrow_mut() = None);
+        STATE.with(|s| *s.borrow_mut() = state);
+        assert_eq!(block_range(3, 1), vec![(3, block(3))]);
+        let next = Block::from(Value::map(vec![
+            ("tid", Value::Nat64(6)),
+            ("phash", Value::blob(block(5).hash().to_vec())),
+        ]));
+        assert_eq!(store_blocks(vec![next.clone()]).map(|_| ()), Ok(()));
+        assert_eq!(block_range(5, 2), vec![(5, block(5)), (6, next)]);
+    }
+
+    #[test]
+    fn upgrades_keep_config_and_blocks() {
+        let raw_memory = DefaultMemoryImpl::default();
+        for index_type in [
+            IndexType::Stable,
+            IndexType::StableTyped,
+            IndexType::Managed,
+        ] {
+            // a fresh canister: empty stable memory and heap
+            raw_memory.borrow_mut().clear();
+            MEMORY_MANAGER.with(|m| *m.borrow_mut() = MemoryManager::init(raw_memory.clone()));
+            BLOCKS.with(|b| *b.borrow_mut() = None);
+            STATE.with(|s| {
+                *s.borrow_mut() = State {
+                    max_records: 10,
+                    block_index_offset: 3,
+                    block_index: 6,
+                    index_type: index_type.clone(),
+                    ..State::default()
+                }
+            });
+            with_blocks_mut(|blocks| {
+                for id in 3..6 {
+                    blocks.insert(id, block(id as u64));
+                }
+            });
+
+            save_state();
+            // the upgrade wipes the heap, only the raw stable memory is kept
+            MEMORY_MANAGER.with(|m| *m.borrow_mut() = MemoryManager::init(raw_memory.clone()));
+            BLOCKS.with(|b| *b.borrow_mut() = None);
+            STATE.with(|s| *s.borrow_mut() = State::default());
+            restore_state();
+
+            with_archive_opts(|opts| {
+                assert_eq!(opts.max_records, 10, "{:?}", index_type);
+                assert_eq!(opts.block_index, 6, "{:?}", index_type);
+            });
+            assert_eq!(
+                with_blocks(|blocks| blocks.range(0..10)),
+                (3..6).map(|id| (id, block(id as u64))).collect::<Vec<_>>(),
+                "{:?}",
+                index_type
+            );
+        }
+    }
+}
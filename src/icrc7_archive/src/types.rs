@@ -1,5 +1,5 @@
 use candid::{CandidType, Principal};
-use icrc_ledger_types::icrc::generic_value::Value;
+use icrc_ledger_types::icrc::generic_value::{Hash, Value};
 
 use serde_derive::{Deserialize, Serialize};
 use std::marker::PhantomData;
@@ -15,6 +15,28 @@ impl Block {
     pub fn value_mut(&mut self) -> &mut Value {
         &mut self.0
     }
+
+    pub fn hash(&self) -> Hash {
+        self.0.hash()
+    }
+
+    // The `phash` field of the block, `None` if the block is not a valid ICRC-3 block.
+    pub fn parent_hash(&self) -> Option<Option<Hash>> {
+        match &self.0 {
+            Value::Map(map) => match map.get("phash") {
+                None => Some(None),
+                Some(Value::Blob(phash)) => phash.as_slice().try_into().ok().map(Some),
+                Some(_) => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl From<Value> for Block {
+    fn from(value: Value) -> Self {
+        Self(value)
+    }
 }
 
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
@@ -30,6 +52,76 @@ pub struct ArchiveInitArgs {
     pub index_type: IndexType,
     pub max_pages: u128,
     pub max_records: u128,
+    pub supported_blocks: Option<Vec<BlockType>>,
+    // hash of the block `first_index - 1`, unknown to ledgers from before it was passed
+    pub parent_hash: Option<Hash>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BlockType {
+    pub block_type: String,
+    pub url: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Standard {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct GetArchiveArgs {
+    pub from: Option<Principal>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetArchivesResultItem {
+    pub canister_id: Principal,
+    pub start: u128,
+    pub end: u128,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveTip {
+    pub last_block_index: u128,
+    pub last_block_hash: Hash,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AppendBlocksError {
+    InsufficientCapacity {
+        remaining: u128,
+        requested: u128,
+    },
+    // The `phash` of the block `index` does not match the hash of the previous block.
+    InvalidParentHash {
+        index: u128,
+        expected: Option<Hash>,
+        found: Option<Hash>,
+    },
+    InsufficientMemory {
+        remaining_bytes: u64,
+        requested_bytes: u64,
+    },
+    MalformedBlock {
+        index: u128,
+    },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RemainingCapacity {
+    pub records: u64,
+    pub bytes: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveStats {
+    pub block_count: u64,
+    pub first_index: u128,
+    pub last_index: Option<u128>,
+    pub stable_memory_pages: u64,
+    pub max_pages: u128,
+    pub cycles: u128,
 }
 
 #[derive(CandidType, Deserialize, Debug)]
@@ -45,13 +137,6 @@ pub struct GetBlocksResult {
     pub archived_blocks: Vec<ArchivedTransactionResponse>,
 }
 
-#[derive(CandidType, Deserialize, Debug)]
-pub struct GetTransactionsResult {
-    pub blocks: Vec<ArchivedTransactionResponse>,
-    pub log_length: u128,
-    pub archived_blocks: Vec<u8>,
-}
-
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
 pub struct TransactionRange {
     pub start: u128,
@@ -64,7 +149,8 @@ pub struct ArchivedTransactionResponse {
     pub callback: QueryTransactionsFn,
 }
 
-pub type QueryTransactionsFn = GetTransactionsFn<Vec<QueryBlock>, GetTransactionsResult>;
+// Archived ranges are fetched with `icrc3_get_blocks` of the archive canister.
+pub type QueryTransactionsFn = GetTransactionsFn<Vec<TransactionRange>, GetBlocksResult>;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(try_from = "candid::types::reference::Func")]
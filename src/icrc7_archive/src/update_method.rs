@@ -1,38 +1,96 @@
 use candid::{candid_method, Principal};
 use ic_cdk_macros::update;
+use ic_stable_structures::Storable;
 
 use crate::{
     guards::owner_guard,
-    state::{with_archive_opts, with_blocks, BLOCK_MAP, STATE},
-    types::Block,
+    state::{
+        remaining_bytes, set_certified_tip, with_archive_opts, with_blocks, with_blocks_mut, STATE,
+    },
+    types::{AppendBlocksError, ArchiveTip, Block},
 };
 
 #[update(guard = "owner_guard")]
 #[candid_method(update)]
-fn append_blocks(new_blocks: Vec<Block>) {
-    let max_records = with_archive_opts(|opts| opts.max_records);
-    let mut block_index = with_archive_opts(|opts| opts.block_index);
-
-    with_blocks(|blocks| {
-        let new_blocks_size = new_blocks.len() as u128;
-        if max_records < (blocks.len() as u128).saturating_add(new_blocks_size) {
-            ic_cdk::api::trap("no space left");
-        }
+pub fn append_blocks(new_blocks: Vec<Block>) -> Result<Option<ArchiveTip>, AppendBlocksError> {
+    let tip = store_blocks(new_blocks)?;
+    if let Some(tip) = &tip {
+        set_certified_tip(tip);
+    }
+    Ok(tip)
+}
+
+// Verifies and stores `new_blocks` after the current tip and returns the new tip.
+pub fn store_blocks(new_blocks: Vec<Block>) -> Result<Option<ArchiveTip>, AppendBlocksError> {
+    let (max_records, first_index, mut last_hash, parent_hash) = with_archive_opts(|opts| {
+        (
+            opts.max_records,
+            opts.block_index,
+            opts.last_hash,
+            opts.parent_hash,
+        )
     });
 
-    for block in new_blocks {
-        block_index = block_index + 1;
-        BLOCK_MAP.with(|p| {
-            let mut block_map = p.borrow_mut();
+    let remaining = max_records.saturating_sub(with_blocks(|blocks| blocks.len()) as u128);
+    let requested = new_blocks.len() as u128;
+    if remaining < requested {
+        return Err(AppendBlocksError::InsufficientCapacity {
+            remaining,
+            requested,
+        });
+    }
 
-            block_map.insert(block_index, block)
+    // The encoded blocks are a lower bound of the stable memory they will take.
+    let remaining_bytes = remaining_bytes();
+    let requested_bytes: u64 = new_blocks
+        .iter()
+        .map(|block| block.to_bytes().len() as u64)
+        .sum();
+    if remaining_bytes < requested_bytes {
+        return Err(AppendBlocksError::InsufficientMemory {
+            remaining_bytes,
+            requested_bytes,
         });
     }
 
-    STATE.with(|s| {
+    // Verify the whole batch before storing anything.
+    for (offset, block) in new_blocks.iter().enumerate() {
+        let index = first_index + offset as u128;
+        let found = block
+            .parent_hash()
+            .ok_or(AppendBlocksError::MalformedBlock { index })?;
+        // An empty archive links its first block to the parent hash it was created with,
+        // or only knows that the first block of the chain has no parent.
+        let expected = last_hash.or(parent_hash);
+        let is_linked = match expected {
+            Some(expected) => found == Some(expected),
+            None => index != 0 || found.is_none(),
+        };
+        if !is_linked {
+            return Err(AppendBlocksError::InvalidParentHash {
+                index,
+                expected,
+                found,
+            });
+        }
+        last_hash = Some(block.hash());
+    }
+
+    // Blocks are keyed by their global block id, `block_index` is the id of the next block.
+    let mut block_index = first_index;
+    with_blocks_mut(|blocks| {
+        for block in new_blocks {
+            blocks.insert(block_index, block);
+            block_index += 1;
+        }
+    });
+
+    Ok(STATE.with(|s| {
         let mut state = s.borrow_mut();
         state.block_index = block_index;
-    })
+        state.last_hash = last_hash;
+        state.tip()
+    }))
 }
 
 #[update(guard = "owner_guard")]
@@ -41,5 +99,133 @@ pub fn update_owner(owner: Principal) -> bool {
         let mut state = s.borrow_mut();
         state.ledger_id = owner;
     });
-    return true;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{block_memory_pages, insert_tip_labels, TREE};
+    use ic_certified_map::AsHashTree;
+    use icrc_ledger_types::icrc::generic_value::{Hash, Value};
+
+    fn block(phash: Option<Hash>, tid: u64) -> Block {
+        let mut block = vec![("tx", Value::map(vec![("tid", Value::Nat64(tid))]))];
+        if let Some(phash) = phash {
+            block.push(("phash", Value::blob(phash.to_vec())));
+        }
+        Block::from(Value::map(block))
+    }
+
+    #[test]
+    fn append_blocks_verifies_hash_chain() {
+        STATE.with(|s| {
+            let mut s = s.borrow_mut();
+            s.max_records = 3;
+            s.max_pages = 1000;
+        });
+
+        let first = block(None, 0);
+        let second = block(Some(first.hash()), 1);
+        assert_eq!(
+            store_blocks(vec![first.clone(), second.clone()]),
+            Ok(Some(ArchiveTip {
+                last_block_index: 1,
+                last_block_hash: second.hash(),
+            }))
+        );
+
+        // a gap in the chain is rejected and nothing is stored
+        let third = block(Some(second.hash()), 2);
+        let fourth = block(Some(third.hash()), 3);
+        assert_eq!(
+            store_blocks(vec![fourth.clone()]),
+            Err(AppendBlocksError::InvalidParentHash {
+                index: 2,
+                expected: Some(second.hash()),
+                found: Some(third.hash()),
+            })
+        );
+        assert_eq!(
+            store_blocks(vec![Block::from(Value::Nat64(2))]),
+            Err(AppendBlocksError::MalformedBlock { index: 2 })
+        );
+        assert_eq!(
+            store_blocks(vec![third.clone(), fourth]),
+            Err(AppendBlocksError::InsufficientCapacity {
+                remaining: 1,
+                requested: 2,
+            })
+        );
+
+        // the page budget is spent once the allocated block memory reaches `max_pages`
+        STATE.with(|s| s.borrow_mut().max_pages = block_memory_pages() as u128);
+        assert_eq!(
+            store_blocks(vec![third.clone()]),
+            Err(AppendBlocksError::InsufficientMemory {
+                remaining_bytes: 0,
+                requested_bytes: third.to_bytes().len() as u64,
+            })
+        );
+        STATE.with(|s| s.borrow_mut().max_pages = 1000);
+
+        assert_eq!(
+            store_blocks(vec![third.clone()]),
+            Ok(Some(ArchiveTip {
+                last_block_index: 2,
+                last_block_hash: third.hash(),
+            }))
+        );
+        assert_eq!(with_blocks(|blocks| blocks.len()), 3);
+
+        // the tip labels follow the last appended block
+        let tip = with_archive_opts(|opts| opts.tip()).unwrap();
+        let root_hash = insert_tip_labels(&tip);
+        TREE.with(|tree| {
+            let tree = tree.borrow();
+            assert_eq!(tree.get(b"last_block_index"), Some(&vec![2]));
+            assert_eq!(tree.get(b"last_block_hash"), Some(&third.hash().to_vec()));
+            assert_eq!(tree.root_hash(), root_hash);
+        });
+    }
+
+    #[test]
+    fn rollover_archive_checks_first_parent_hash() {
+        let previous = block(None, 4);
+        STATE.with(|s| {
+            let mut s = s.borrow_mut();
+            s.max_records = 3;
+            s.max_pages = 1000;
+            s.block_index_offset = 5;
+            s.block_index = 5;
+            s.parent_hash = Some(previous.hash());
+        });
+
+        let forged = block(Some([1; 32]), 5);
+        assert_eq!(
+            store_blocks(vec![forged.clone()]),
+            Err(AppendBlocksError::InvalidParentHash {
+                index: 5,
+                expected: Some(previous.hash()),
+                found: Some([1; 32]),
+            })
+        );
+        assert_eq!(
+            store_blocks(vec![block(None, 5)]),
+            Err(AppendBlocksError::InvalidParentHash {
+                index: 5,
+                expected: Some(previous.hash()),
+                found: None,
+            })
+        );
+
+        let first = block(Some(previous.hash()), 5);
+        assert_eq!(
+            store_blocks(vec![first.clone()]),
+            Ok(Some(ArchiveTip {
+                last_block_index: 5,
+                last_block_hash: first.hash(),
+            }))
+        );
+    }
 }
@@ -1,6 +1,13 @@
-use crate::update_method::Arg;
+use crate::types::{
+    CollectionInfo, CollectionMetadata, CollectionPage, CollectionSettings,
+    CollectionUpgradeStatus, HandoverArg, InitialToken, Launch, LaunchConfig, LaunchError,
+    LaunchPayment, LaunchPrice, LaunchTemplate, LaunchUsage, MintJob, MonitorConfig,
+    MonitoringStatus, Rollout, SearchCollectionsArg, TopUp, TopUpPolicy, WasmInfo,
+};
 use candid::{export_service, Principal};
 use ic_cdk_macros::query;
+use icrc7_types::icrc7_types::{InitArg, UpgradeArg};
+use icrc_ledger_types::icrc1::account::Account;
 
 #[query(name = "__get_candid_interface_tmp_hack")]
 fn export_candid() -> String {
@@ -0,0 +1,101 @@
+use std::{cell::RefCell, time::Duration};
+
+use candid::Principal;
+use ic_cdk::api::call::CallResult;
+use icrc7_types::icrc7_types::Standard;
+
+use crate::{guards::RunGuard, state::STATE, types::CollectionMetadata};
+
+pub const METADATA_REFRESH_SECONDS: u64 = 60 * 60;
+
+thread_local! {
+    static REFRESH_RUNNING: RefCell<bool> = RefCell::default();
+}
+
+// Refreshes the metadata cache every `METADATA_REFRESH_SECONDS`, and once right away.
+pub fn schedule_metadata_refresh() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(refresh_all_metadata()));
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(METADATA_REFRESH_SECONDS), || {
+        ic_cdk::spawn(refresh_all_metadata())
+    });
+}
+
+pub fn schedule_collection_refresh(canister_id: Principal) {
+    ic_cdk_timers::set_timer(Duration::ZERO, move || {
+        ic_cdk::spawn(refresh_collection_metadata(canister_id))
+    });
+}
+
+// Reads the metadata of every registered collection. Does nothing while a previous run is
+// in progress.
+pub async fn refresh_all_metadata() {
+    let Some(_running) = RunGuard::acquire(&REFRESH_RUNNING) else {
+        return;
+    };
+    let mut prev = None;
+    loop {
+        let collections = STATE.with(|s| s.borrow().list_collections(prev, None));
+        let Some(last) = collections.last() else {
+            break;
+        };
+        prev = Some(last.canister_id);
+        for info in collections {
+            refresh_collection_metadata(info.canister_id).await;
+        }
+    }
+}
+
+async fn refresh_collection_metadata(canister_id: Principal) {
+    let cached = STATE.with(|s| {
+        let s = s.borrow();
+        s.get_collection_metadata(&canister_id).or_else(|| {
+            s.get_collection(&canister_id)
+                .map(|info| CollectionMetadata::from_info(&info))
+        })
+    });
+    let Some(mut metadata) = cached else {
+        return;
+    };
+    match read_metadata(canister_id, metadata.created_at).await {
+        Ok(read) => metadata = read,
+        Err((code, msg)) => metadata.error = Some(format!("Code: {:?}, Message: {:?}", code, msg)),
+    }
+    metadata.refreshed_at = ic_cdk::api::time();
+    STATE.with(|s| s.borrow_mut().set_collection_metadata(metadata));
+}
+
+async fn read_metadata(canister_id: Principal, created_at: u64) -> CallResult<CollectionMetadata> {
+    let (name,) = ic_cdk::call::<_, (String,)>(canister_id, "icrc7_name", ()).await?;
+    let (symbol,) = ic_cdk::call::<_, (String,)>(canister_id, "icrc7_symbol", ()).await?;
+    let (logo,) = ic_cdk::call::<_, (Option<String>,)>(canister_id, "icrc7_logo", ()).await?;
+    let (total_supply,) = ic_cdk::call::<_, (u128,)>(canister_id, "icrc7_total_supply", ()).await?;
+    let (supply_cap,) =
+        ic_cdk::call::<_, (Option<u128>,)>(canister_id, "icrc7_supply_cap", ()).await?;
+    // ledgers from before ICRC-10 only answer the ICRC-7 method
+    let standards =
+        match ic_cdk::call::<_, (Vec<Standard>,)>(canister_id, "icrc10_supported_standards", ())
+            .await
+        {
+            Ok((standards,)) => standards,
+            Err(_) => {
+                ic_cdk::call::<_, (Vec<Standard>,)>(canister_id, "icrc7_supported_standards", ())
+                    .await?
+                    .0
+            }
+        };
+    Ok(CollectionMetadata {
+        canister_id,
+        name,
+        symbol,
+        logo,
+        total_supply,
+        supply_cap,
+        standards: standards
+            .into_iter()
+            .map(|standard| standard.name)
+            .collect(),
+        created_at,
+        refreshed_at: 0,
+        error: None,
+    })
+}
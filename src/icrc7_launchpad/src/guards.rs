@@ -0,0 +1,52 @@
+use std::{cell::RefCell, thread::LocalKey};
+
+use ic_cdk::caller;
+
+#[inline(always)]
+pub fn admin_guard() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&caller()) {
+        Ok(())
+    } else {
+        Err(String::from(
+            "The caller is not a controller of the launchpad",
+        ))
+    }
+}
+
+// Marks a timer job as running until dropped. The futures of a trapped call are dropped
+// too, so the job isn't stuck as running after a trap.
+pub struct RunGuard(&'static LocalKey<RefCell<bool>>);
+
+impl RunGuard {
+    // None while another run holds `running`.
+    pub fn acquire(running: &'static LocalKey<RefCell<bool>>) -> Option<Self> {
+        if running.with(|r| r.replace(true)) {
+            return None;
+        }
+        Some(Self(running))
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.0.with(|r| *r.borrow_mut() = false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    thread_local! {
+        static RUNNING: RefCell<bool> = RefCell::default();
+    }
+
+    #[test]
+    fn runs_are_released_on_drop() {
+        let guard = RunGuard::acquire(&RUNNING);
+        assert!(guard.is_some());
+        assert!(RunGuard::acquire(&RUNNING).is_none());
+        drop(guard);
+        assert!(RunGuard::acquire(&RUNNING).is_some());
+    }
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use ic_cdk_macros::{init, post_upgrade};
+
+use crate::{
+    discovery::schedule_metadata_refresh, minting::schedule_mint_batch,
+    monitoring::schedule_monitoring, rollout::schedule_rollout_batch, state::STATE,
+    update_method::abort_interrupted_launch,
+};
+
+#[init]
+fn init() {
+    STATE.with(|s| s.borrow_mut().seed_default_templates());
+    schedule_monitoring();
+    schedule_metadata_refresh();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    // launchpads from before templates start with the default ones
+    STATE.with(|s| s.borrow_mut().seed_default_templates());
+    // timers don't survive upgrades, pick running jobs up at their cursor
+    if STATE
+        .with(|s| s.borrow().rollout())
+        .is_some_and(|rollout| rollout.running)
+    {
+        schedule_rollout_batch();
+    }
+    for canister_id in STATE.with(|s| s.borrow().running_mint_jobs()) {
+        schedule_mint_batch(canister_id);
+    }
+    // launches don't survive upgrades either, their fees are refunded and their canisters
+    // reclaimed
+    let launches = STATE.with(|s| {
+        s.borrow_mut()
+            .recover_interrupted_launches(ic_cdk::api::is_controller, ic_cdk::api::time())
+    });
+    for launch in launches {
+        ic_cdk_timers::set_timer(Duration::ZERO, move || {
+            ic_cdk::spawn(abort_interrupted_launch(launch))
+        });
+    }
+    schedule_monitoring();
+    schedule_metadata_refresh();
+}
@@ -0,0 +1,339 @@
+use candid::{Encode, Principal};
+use ic_cdk::api::{
+    call::RejectionCode,
+    management_canister::main::{
+        canister_status, create_canister, delete_canister, deposit_cycles, install_code,
+        stop_canister, uninstall_code, update_settings, CanisterIdRecord, CanisterInstallMode,
+        CanisterSettings, CreateCanisterArgument, InstallCodeArgument, UpdateSettingsArgument,
+    },
+};
+
+use crate::{
+    monitoring::list_archives,
+    state::STATE,
+    types::{
+        CollectionInfo, CollectionSettings, Handover, HandoverArg, LaunchError, LaunchStatus,
+        PendingHandover,
+    },
+    update_method::{ASSETS_WASM, REFUND_WASM},
+};
+
+pub const MAX_INSTALL_ATTEMPTS: u32 = 3;
+// Empty canisters kept for later launches, the ones above are deleted
+pub const MAX_SPARE_CANISTERS: u64 = 10;
+// the IC limit of controllers per canister
+pub const MAX_CONTROLLERS: usize = 10;
+
+fn controllers_settings(controllers: Vec<Principal>) -> CanisterSettings {
+    CanisterSettings {
+        controllers: Some(controllers),
+        compute_allocation: None,
+        memory_allocation: None,
+        freezing_threshold: None,
+        reserved_cycles_limit: None,
+    }
+}
+
+// A canister with `settings` holding at least `cycles`, a spare one when there is any.
+pub async fn acquire_canister(
+    settings: CanisterSettings,
+    cycles: u128,
+) -> Result<Principal, LaunchError> {
+    if let Some(canister_id) = STATE.with(|s| s.borrow_mut().pop_spare_canister()) {
+        match prepare_spare_canister(canister_id, settings.clone(), cycles).await {
+            Ok(()) => return Ok(canister_id),
+            Err((code, msg)) => {
+                ic_cdk::println!("acquire_canister: {} {:?} {}", canister_id, code, msg);
+                STATE.with(|s| s.borrow_mut().push_spare_canister(canister_id));
+            }
+        }
+    }
+    create_canister(
+        CreateCanisterArgument {
+            settings: Some(settings),
+        },
+        cycles,
+    )
+    .await
+    .map(|(record,)| record.canister_id)
+    .map_err(|(code, message)| LaunchError::CreateCanisterFailed { code, message })
+}
+
+// Tops a spare canister up to `cycles` and applies the settings of the new collection.
+async fn prepare_spare_canister(
+    canister_id: Principal,
+    settings: CanisterSettings,
+    cycles: u128,
+) -> ic_cdk::api::call::CallResult<()> {
+    let (status,) = canister_status(CanisterIdRecord { canister_id }).await?;
+    let balance = u128::try_from(status.cycles.0).unwrap_or(u128::MAX);
+    if balance < cycles {
+        deposit_cycles(CanisterIdRecord { canister_id }, cycles - balance).await?;
+    }
+    update_settings(UpdateSettingsArgument {
+        canister_id,
+        settings,
+    })
+    .await
+}
+
+// Removes the launchpad from the controllers of an installed collection, returns whether
+// it left.
+pub async fn leave_canister(
+    canister_id: Principal,
+    settings: &CollectionSettings,
+    creator: Principal,
+) -> bool {
+    let controllers = settings.controllers(ic_cdk::id(), creator);
+    if let Err((code, msg)) = update_settings(UpdateSettingsArgument {
+        canister_id,
+        settings: controllers_settings(controllers),
+    })
+    .await
+    {
+        ic_cdk::println!("leave_canister: {} {:?} {}", canister_id, code, msg);
+        return false;
+    }
+    true
+}
+
+// Gives the control of a collection to the controllers of `arg`. The minting authority is
+// set first, while the launchpad is still a controller of the ledger. The archives keep the
+// ledger as a controller so that it can keep writing to them. Each step is recorded, so that
+// a handover that stops part way is resumed by a retry with the same argument.
+pub async fn hand_over_collection(
+    mut info: CollectionInfo,
+    arg: HandoverArg,
+    caller: Principal,
+) -> Result<CollectionInfo, String> {
+    let arg = arg.normalize(ic_cdk::id())?;
+    let canister_id = info.canister_id;
+    let mut pending = match info.pending_handover.clone() {
+        Some(pending) if pending.arg == arg => pending,
+        // the launchpad may not control the canisters already handed over anymore
+        Some(pending) if !pending.done.is_empty() => {
+            return Err("Another handover of the collection stopped part way, retry it with the same argument".into());
+        }
+        _ => PendingHandover {
+            arg: arg.clone(),
+            minting_authority_set: false,
+            done: vec![],
+            by: caller,
+            started_at: ic_cdk::api::time(),
+        },
+    };
+    save_handover_progress(&mut info, &pending);
+
+    if let Some(minting_account) = arg.minting_account {
+        if !pending.minting_authority_set {
+            ic_cdk::call::<_, (bool,)>(canister_id, "set_minting_authority", (minting_account,))
+                .await
+                .map_err(|(code, msg)| format!("Code: {:?}, Message: {:?}", code, msg))?;
+            pending.minting_authority_set = true;
+            save_handover_progress(&mut info, &pending);
+        }
+    }
+    // the archives and the asset canister first, the ledger lists its archives
+    let controllers = arg.controllers.clone();
+    let mut archive_controllers = vec![canister_id];
+    archive_controllers.extend(controllers.iter().cloned());
+    let mut steps: Vec<(Principal, Vec<Principal>)> = list_archives(canister_id)
+        .await
+        .into_iter()
+        .map(|archive| (archive, archive_controllers.clone()))
+        .collect();
+    if let Some(asset_canister) = info.asset_canister {
+        steps.push((asset_canister, controllers.clone()));
+    }
+    steps.push((canister_id, controllers.clone()));
+    for (target, target_controllers) in steps {
+        if pending.done.contains(&target) {
+            continue;
+        }
+        set_controllers(target, target_controllers).await?;
+        pending.done.push(target);
+        save_handover_progress(&mut info, &pending);
+    }
+
+    info.pending_handover = None;
+    info.handover = Some(Handover {
+        controllers,
+        launchpad_controller: arg.keep_launchpad,
+        minting_account: arg.minting_account,
+        by: caller,
+        at: ic_cdk::api::time(),
+    });
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        if !arg.keep_launchpad {
+            s.forget_canister_cycles(&canister_id);
+        }
+        s.register_collection(info.clone());
+    });
+    Ok(info)
+}
+
+fn save_handover_progress(info: &mut CollectionInfo, pending: &PendingHandover) {
+    info.pending_handover = Some(pending.clone());
+    STATE.with(|s| s.borrow_mut().register_collection(info.clone()));
+}
+
+async fn set_controllers(
+    canister_id: Principal,
+    controllers: Vec<Principal>,
+) -> Result<(), String> {
+    update_settings(UpdateSettingsArgument {
+        canister_id,
+        settings: controllers_settings(controllers),
+    })
+    .await
+    .map_err(|(code, msg)| {
+        format!(
+            "Updating the controllers of {} failed. Code: {:?}, Message: {:?}",
+            canister_id, code, msg
+        )
+    })
+}
+
+// Installs the collection, retrying transient failures up to `MAX_INSTALL_ATTEMPTS` times.
+// The canister of a failed install is reclaimed.
+pub async fn install_collection(
+    launch_id: u64,
+    canister_id: Principal,
+    wasm_module: Vec<u8>,
+    arg: Vec<u8>,
+) -> Result<(), LaunchError> {
+    let mut attempt = 1;
+    loop {
+        STATE.with(|s| {
+            s.borrow_mut().set_launch_status(
+                launch_id,
+                LaunchStatus::Installing {
+                    canister_id,
+                    attempt,
+                },
+                ic_cdk::api::time(),
+            )
+        });
+        let result = install_code(InstallCodeArgument {
+            mode: CanisterInstallMode::Install,
+            canister_id,
+            wasm_module: wasm_module.clone(),
+            arg: arg.clone(),
+        })
+        .await;
+        match result {
+            Ok(()) => return Ok(()),
+            Err((RejectionCode::SysTransient, message)) if attempt < MAX_INSTALL_ATTEMPTS => {
+                ic_cdk::println!(
+                    "install_collection: {} {:?} {}",
+                    canister_id,
+                    RejectionCode::SysTransient,
+                    message
+                );
+                attempt += 1
+            }
+            Err((code, message)) => {
+                reclaim_canister(canister_id).await;
+                return Err(LaunchError::InstallCodeFailed {
+                    canister_id,
+                    attempts: attempt,
+                    code,
+                    message,
+                });
+            }
+        }
+    }
+}
+
+// Installs `ASSETS_WASM` into the asset canister of a collection, its controllers are its
+// admins.
+pub async fn install_assets(canister_id: Principal) -> Result<(), LaunchError> {
+    let result = install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id,
+        wasm_module: ASSETS_WASM.to_vec(),
+        arg: Encode!().unwrap(),
+    })
+    .await;
+    if let Err((code, message)) = result {
+        reclaim_canister(canister_id).await;
+        return Err(LaunchError::AssetInstallFailed {
+            canister_id,
+            code,
+            message,
+        });
+    }
+    Ok(())
+}
+
+// Takes the canister of a failed launch back from its creator and empties it, so that
+// its cycles go to the next launch. Past `MAX_SPARE_CANISTERS` its cycles are refunded to
+// the launchpad and it is deleted.
+pub async fn reclaim_canister(canister_id: Principal) {
+    if STATE.with(|s| s.borrow().spare_canister_count()) < MAX_SPARE_CANISTERS {
+        let result = async {
+            update_settings(UpdateSettingsArgument {
+                canister_id,
+                settings: controllers_settings(vec![ic_cdk::id()]),
+            })
+            .await?;
+            uninstall_code(CanisterIdRecord { canister_id }).await
+        }
+        .await;
+        match result {
+            Ok(()) => {
+                STATE.with(|s| s.borrow_mut().push_spare_canister(canister_id));
+                return;
+            }
+            Err((code, msg)) => {
+                ic_cdk::println!("reclaim_canister: {} {:?} {}", canister_id, code, msg)
+            }
+        }
+    }
+    if let Err(err) = refund_canister(canister_id).await {
+        // a canister whose cycles can't be refunded is kept rather than burnt
+        ic_cdk::println!("reclaim_canister: {} {}", canister_id, err);
+        if uninstall_code(CanisterIdRecord { canister_id })
+            .await
+            .is_ok()
+        {
+            STATE.with(|s| s.borrow_mut().push_spare_canister(canister_id));
+        }
+        return;
+    }
+    let result = async {
+        stop_canister(CanisterIdRecord { canister_id }).await?;
+        delete_canister(CanisterIdRecord { canister_id }).await
+    }
+    .await;
+    if let Err((code, msg)) = result {
+        ic_cdk::println!("reclaim_canister: {} {:?} {}", canister_id, code, msg);
+    }
+}
+
+// Installs `REFUND_WASM` into a canister of the launchpad and has it deposit its cycles
+// back to the launchpad.
+async fn refund_canister(canister_id: Principal) -> Result<u128, String> {
+    let mut settings = controllers_settings(vec![ic_cdk::id()]);
+    // all the cycles above the call fees are refunded
+    settings.freezing_threshold = Some(0u8.into());
+    update_settings(UpdateSettingsArgument {
+        canister_id,
+        settings,
+    })
+    .await
+    .map_err(|(code, msg)| format!("Code: {:?}, Message: {:?}", code, msg))?;
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Reinstall,
+        canister_id,
+        wasm_module: REFUND_WASM.to_vec(),
+        arg: Encode!().unwrap(),
+    })
+    .await
+    .map_err(|(code, msg)| format!("Code: {:?}, Message: {:?}", code, msg))?;
+    let (result,) = ic_cdk::call::<_, (Result<u128, String>,)>(canister_id, "refund", ())
+        .await
+        .map_err(|(code, msg)| format!("Code: {:?}, Message: {:?}", code, msg))?;
+    result
+}
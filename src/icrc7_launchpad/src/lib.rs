@@ -1,5 +1,18 @@
 use ic_cdk_macros::export_candid;
 
+pub mod types;
+pub mod memory;
+pub mod state;
+pub mod guards;
+pub mod init_method;
+pub mod discovery;
+pub mod launch;
+pub mod minting;
+pub mod monitoring;
+pub mod rollout;
+pub mod templates;
+pub mod payment;
+pub mod query_method;
 pub mod update_method;
 pub mod candid_file_generator;
 
@@ -0,0 +1,113 @@
+use crate::{
+    state::MEMORY_MANAGER,
+    types::{
+        CanisterCycles, CollectionInfo, CollectionMetadata, CollectionUpgradeStatus, InitialToken,
+        Launch, LaunchConfig, LaunchTemplate, LaunchUsage, MintJob, MonitorConfig, Rollout, TopUp,
+        TopUpPolicy, WasmInfo, WasmUpload,
+    },
+};
+use candid::Principal;
+use ic_stable_structures::{
+    memory_manager::{MemoryId, VirtualMemory},
+    DefaultMemoryImpl, StableBTreeMap, StableCell,
+};
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+pub fn get_collections_memory() -> StableBTreeMap<Principal, CollectionInfo, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))))
+}
+
+// (creator, canister id) pairs, so the collections of a creator are one range scan
+pub fn get_creator_index_memory() -> StableBTreeMap<(Principal, Principal), (), Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))))
+}
+
+pub fn get_wasm_info_memory() -> StableBTreeMap<String, WasmInfo, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))))
+}
+
+pub fn get_wasm_module_memory() -> StableBTreeMap<String, Vec<u8>, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))))
+}
+
+pub fn get_wasm_upload_memory() -> StableBTreeMap<String, WasmUpload, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))))
+}
+
+pub fn get_upgrade_status_memory() -> StableBTreeMap<Principal, CollectionUpgradeStatus, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))))
+}
+
+pub fn get_rollout_memory() -> StableCell<Option<Rollout>, Memory> {
+    StableCell::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))),
+        None,
+    )
+    .expect("failed to init the rollout memory")
+}
+
+pub fn get_launch_config_memory() -> StableCell<LaunchConfig, Memory> {
+    StableCell::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))),
+        LaunchConfig::default(),
+    )
+    .expect("failed to init the launch config memory")
+}
+
+pub fn get_launch_usage_memory() -> StableBTreeMap<Principal, LaunchUsage, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))))
+}
+
+pub fn get_launches_memory() -> StableBTreeMap<u64, Launch, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))))
+}
+
+// (creator, launch id) pairs
+pub fn get_launch_creator_index_memory() -> StableBTreeMap<(Principal, u64), (), Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))))
+}
+
+// Empty canisters left by failed launches, reused by the next ones
+pub fn get_spare_canisters_memory() -> StableBTreeMap<Principal, (), Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))))
+}
+
+pub fn get_mint_jobs_memory() -> StableBTreeMap<Principal, MintJob, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))))
+}
+
+// The tokens left to mint, by (collection, index in the job)
+pub fn get_mint_queue_memory() -> StableBTreeMap<(Principal, u64), InitialToken, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))))
+}
+
+pub fn get_templates_memory() -> StableBTreeMap<String, LaunchTemplate, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))))
+}
+
+pub fn get_monitor_config_memory() -> StableCell<MonitorConfig, Memory> {
+    StableCell::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))),
+        MonitorConfig::default(),
+    )
+    .expect("failed to init the monitor config memory")
+}
+
+// Top-up policies set for single collections, their archives included
+pub fn get_top_up_policies_memory() -> StableBTreeMap<Principal, TopUpPolicy, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))))
+}
+
+pub fn get_canister_cycles_memory() -> StableBTreeMap<Principal, CanisterCycles, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))))
+}
+
+pub fn get_top_ups_memory() -> StableBTreeMap<u64, TopUp, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))))
+}
+
+// The metadata cache, refreshed from the ledgers by a timer
+pub fn get_collection_metadata_memory() -> StableBTreeMap<Principal, CollectionMetadata, Memory> {
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))))
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use candid::Principal;
+use icrc7_types::icrc7_types::{MintArg, MintResult};
+use icrc_ledger_types::icrc1::account::Account;
+
+use crate::{
+    discovery::schedule_collection_refresh,
+    state::STATE,
+    types::{FailedMint, InitialToken, LaunchError, MintJob},
+};
+
+pub const MINT_BATCH_SIZE: u64 = 20;
+pub const MAX_INITIAL_TOKENS: usize = 10_000;
+
+// Initial tokens are minted in increasing token id order, as the ledger requires.
+pub fn validate_initial_tokens(
+    tokens: &mut [InitialToken],
+    supply_cap: Option<u128>,
+) -> Result<(), LaunchError> {
+    if tokens.len() > MAX_INITIAL_TOKENS {
+        return Err(LaunchError::InvalidInitialTokens(format!(
+            "At most {} initial tokens",
+            MAX_INITIAL_TOKENS
+        )));
+    }
+    if supply_cap.is_some_and(|cap| tokens.len() as u128 > cap) {
+        return Err(LaunchError::InvalidInitialTokens(
+            "More initial tokens than the supply cap".into(),
+        ));
+    }
+    tokens.sort_by_key(|token| token.token_id);
+    if let Some(pair) = tokens
+        .windows(2)
+        .find(|pair| pair[0].token_id == pair[1].token_id)
+    {
+        return Err(LaunchError::InvalidInitialTokens(format!(
+            "Duplicate token id {}",
+            pair[0].token_id
+        )));
+    }
+    Ok(())
+}
+
+pub fn schedule_mint_batch(canister_id: Principal) {
+    ic_cdk_timers::set_timer(Duration::ZERO, move || {
+        ic_cdk::spawn(run_mint_batch(canister_id))
+    });
+}
+
+// Mints the next batch of the job of `canister_id` and schedules the one after it. A
+// rejected call pauses the job at the token it was minting.
+async fn run_mint_batch(canister_id: Principal) {
+    let job = match STATE.with(|s| s.borrow().mint_job(&canister_id)) {
+        Some(job) if job.running => job,
+        _ => return,
+    };
+    let batch = STATE.with(|s| s.borrow().mint_batch(&job, MINT_BATCH_SIZE));
+    if batch.is_empty() {
+        finish_mint_job(job).await;
+        return;
+    }
+
+    for (index, token) in batch {
+        let token_id = token.token_id;
+        let result =
+            ic_cdk::call::<_, (MintResult,)>(canister_id, "mint", (MintArg::from(token),)).await;
+        let result = match result {
+            Ok((Ok(_),)) => Ok(()),
+            Ok((Err(error),)) => Err(FailedMint { token_id, error }),
+            Err((code, msg)) => {
+                pause_mint_job(canister_id, format!("Code: {:?}, Message: {:?}", code, msg));
+                return;
+            }
+        };
+        STATE.with(|s| s.borrow_mut().record_mint(canister_id, index, result));
+    }
+
+    schedule_mint_batch(canister_id);
+}
+
+// Hands the minting authority of the collection over once every token is minted.
+async fn finish_mint_job(mut job: MintJob) {
+    let result = ic_cdk::call::<_, (bool,)>(
+        job.canister_id,
+        "set_minting_authority",
+        (job.minting_account,),
+    )
+    .await;
+    match result {
+        Ok(_) => {
+            job.running = false;
+            job.last_error = None;
+            job.finished_at = Some(ic_cdk::api::time());
+            schedule_collection_refresh(job.canister_id);
+            STATE.with(|s| s.borrow_mut().set_mint_job(job));
+        }
+        Err((code, msg)) => pause_mint_job(
+            job.canister_id,
+            format!("Code: {:?}, Message: {:?}", code, msg),
+        ),
+    }
+}
+
+fn pause_mint_job(canister_id: Principal, error: String) {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        if let Some(mut job) = s.mint_job(&canister_id) {
+            job.running = false;
+            job.last_error = Some(error);
+            s.set_mint_job(job);
+        }
+    });
+}
+
+// The account the launchpad mints the initial tokens from.
+pub fn launchpad_minting_account() -> Account {
+    Account {
+        owner: ic_cdk::id(),
+        subaccount: None,
+    }
+}
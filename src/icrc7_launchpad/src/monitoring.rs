@@ -0,0 +1,150 @@
+use std::{cell::RefCell, time::Duration};
+
+use candid::Principal;
+use ic_cdk::api::management_canister::main::{
+    canister_info, canister_status, deposit_cycles, CanisterIdRecord, CanisterInfoRequest,
+};
+use ic_cdk_timers::TimerId;
+use icrc7_types::icrc3_types::{GetArchiveArgs, GetArchivesResultItem};
+
+use crate::{
+    guards::RunGuard,
+    state::STATE,
+    types::{CanisterCycles, MonitoredKind, TopUp},
+};
+
+thread_local! {
+    static MONITOR_TIMER: RefCell<Option<TimerId>> = RefCell::default();
+    static MONITOR_RUNNING: RefCell<bool> = RefCell::default();
+}
+
+// Starts the monitoring timer with the current config, replacing the previous one.
+pub fn schedule_monitoring() {
+    if let Some(timer_id) = MONITOR_TIMER.with(|t| t.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer_id);
+    }
+    let config = STATE.with(|s| s.borrow().monitor_config());
+    if !config.enabled {
+        return;
+    }
+    let timer_id =
+        ic_cdk_timers::set_timer_interval(Duration::from_secs(config.interval_seconds), || {
+            ic_cdk::spawn(run_monitoring())
+        });
+    MONITOR_TIMER.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+// Checks every registered collection and its archives, and tops up the ones below the
+// threshold of their collection. Does nothing while a previous run is in progress.
+pub async fn run_monitoring() {
+    let Some(_running) = RunGuard::acquire(&MONITOR_RUNNING) else {
+        return;
+    };
+    let mut prev = None;
+    loop {
+        let collections = STATE.with(|s| s.borrow().list_collections(prev, None));
+        let Some(last) = collections.last() else {
+            break;
+        };
+        prev = Some(last.canister_id);
+        for info in collections
+            .into_iter()
+            .filter(|info| !info.is_handed_over())
+        {
+            check_canister(
+                info.canister_id,
+                info.canister_id,
+                MonitoredKind::Collection,
+            )
+            .await;
+            for archive in list_archives(info.canister_id).await {
+                check_canister(archive, info.canister_id, MonitoredKind::Archive).await;
+            }
+        }
+    }
+}
+
+// The archives of a collection, empty when the ledger can't be reached.
+pub async fn list_archives(collection: Principal) -> Vec<Principal> {
+    let result = ic_cdk::call::<_, (Vec<GetArchivesResultItem>,)>(
+        collection,
+        "icrc3_get_archives",
+        (GetArchiveArgs { from: None },),
+    )
+    .await;
+    match result {
+        // the ledger lists itself first
+        Ok((archives,)) => archives
+            .into_iter()
+            .map(|archive| archive.canister_id)
+            .filter(|canister_id| *canister_id != collection)
+            .collect(),
+        Err((code, msg)) => {
+            ic_cdk::println!("list_archives: {} {:?} {}", collection, code, msg);
+            vec![]
+        }
+    }
+}
+
+// Whether the launchpad is known not to be a controller of `canister_id`.
+async fn is_uncontrolled(canister_id: Principal) -> bool {
+    canister_info(CanisterInfoRequest {
+        canister_id,
+        num_requested_changes: None,
+    })
+    .await
+    .is_ok_and(|(info,)| !info.controllers.contains(&ic_cdk::id()))
+}
+
+async fn check_canister(canister_id: Principal, collection: Principal, kind: MonitoredKind) {
+    let policy = STATE.with(|s| s.borrow().top_up_policy(&collection));
+    let (cycles, error) = match canister_status(CanisterIdRecord { canister_id }).await {
+        Ok((status,)) => (
+            Some(u128::try_from(status.cycles.0).unwrap_or(u128::MAX)),
+            None,
+        ),
+        // only controllers get the status of a canister, the other ones aren't monitored
+        Err(_) if is_uncontrolled(canister_id).await => {
+            STATE.with(|s| s.borrow_mut().remove_canister_cycles(&canister_id));
+            return;
+        }
+        Err((code, msg)) => (None, Some(format!("Code: {:?}, Message: {:?}", code, msg))),
+    };
+    let mut checked = CanisterCycles {
+        canister_id,
+        collection,
+        kind,
+        cycles,
+        threshold: policy.threshold,
+        error,
+        checked_at: ic_cdk::api::time(),
+    };
+
+    if let Some(balance) = cycles.filter(|cycles| *cycles < policy.threshold) {
+        let reserve = STATE.with(|s| s.borrow().monitor_config().reserve_cycles);
+        let error = if ic_cdk::api::canister_balance128().saturating_sub(policy.amount) < reserve {
+            Some("The launchpad balance would go below its reserve".to_string())
+        } else {
+            match deposit_cycles(CanisterIdRecord { canister_id }, policy.amount).await {
+                Ok(()) => {
+                    checked.cycles = Some(balance.saturating_add(policy.amount));
+                    None
+                }
+                Err((code, msg)) => Some(format!("Code: {:?}, Message: {:?}", code, msg)),
+            }
+        };
+        checked.error = error.clone();
+        STATE.with(|s| {
+            s.borrow_mut().record_top_up(TopUp {
+                id: 0,
+                canister_id,
+                collection,
+                amount: policy.amount,
+                cycles: balance,
+                error,
+                at: ic_cdk::api::time(),
+            })
+        });
+    }
+    STATE.with(|s| s.borrow_mut().record_canister_cycles(checked));
+}
@@ -0,0 +1,149 @@
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::{msg_cycles_accept128, msg_cycles_available128};
+use icrc_ledger_types::{
+    icrc1::{
+        account::{Account, Subaccount},
+        transfer::{TransferArg, TransferError},
+    },
+    icrc2::transfer_from::{TransferFromArgs, TransferFromError},
+};
+
+use serde::Deserialize;
+
+use crate::types::{LaunchPayment, LaunchPrice};
+
+// A launch fee taken from the creator, given back when the launch fails.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum LaunchFee {
+    // the attached cycles are only accepted once the collection is installed
+    Cycles(u128),
+    Tokens { ledger: Principal, amount: u128 },
+}
+
+// The subaccount of the launchpad a principal deposits its launch fees to.
+pub fn deposit_subaccount(owner: &Principal) -> Subaccount {
+    let bytes = owner.as_slice();
+    let mut subaccount = [0u8; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+    subaccount
+}
+
+pub fn deposit_account(owner: &Principal) -> Account {
+    Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(deposit_subaccount(owner)),
+    }
+}
+
+fn launchpad_account() -> Account {
+    Account {
+        owner: ic_cdk::id(),
+        subaccount: None,
+    }
+}
+
+pub async fn collect_launch_fee(
+    caller: Principal,
+    payment: LaunchPayment,
+    price: &LaunchPrice,
+) -> Result<LaunchFee, String> {
+    let (ledger, amount) = match (payment, price.payment_ledger, price.tokens) {
+        (LaunchPayment::Cycles, _, _) => {
+            if msg_cycles_available128() < price.cycles {
+                return Err(format!("A launch costs {} attached cycles", price.cycles));
+            }
+            return Ok(LaunchFee::Cycles(price.cycles));
+        }
+        (_, Some(ledger), Some(amount)) => (ledger, amount),
+        _ => return Err("Token payments are not enabled".into()),
+    };
+
+    let result = match payment {
+        LaunchPayment::Icrc2Approval => {
+            let args = TransferFromArgs {
+                spender_subaccount: None,
+                from: Account {
+                    owner: caller,
+                    subaccount: None,
+                },
+                to: launchpad_account(),
+                amount: Nat::from(amount),
+                fee: None,
+                memo: None,
+                created_at_time: None,
+            };
+            ic_cdk::call::<_, (Result<Nat, TransferFromError>,)>(
+                ledger,
+                "icrc2_transfer_from",
+                (args,),
+            )
+            .await
+            .map_err(|(code, msg)| format!("Code: {:?}, Message: {:?}", code, msg))?
+            .0
+            .map_err(|err| format!("{:?}", err))
+        }
+        _ => transfer(
+            ledger,
+            Some(deposit_subaccount(&caller)),
+            launchpad_account(),
+            amount,
+        )
+        .await
+        .map_err(|err| format!("{:?}", err)),
+    };
+    result.map(|_| LaunchFee::Tokens { ledger, amount })
+}
+
+// Keeps a fee paid with cycles, the launch went through.
+pub fn accept_launch_fee(fee: &LaunchFee) {
+    if let LaunchFee::Cycles(cycles) = fee {
+        msg_cycles_accept128(*cycles);
+    }
+}
+
+// Sends a token fee back to the creator, minus the ledger fee of the refund.
+pub async fn refund_launch_fee(caller: Principal, fee: LaunchFee) {
+    // attached cycles that weren't accepted go back with the reply
+    if let LaunchFee::Tokens { ledger, amount } = fee {
+        let ledger_fee = match ic_cdk::call::<_, (Nat,)>(ledger, "icrc1_fee", ()).await {
+            Ok((ledger_fee,)) => ledger_fee,
+            Err((code, msg)) => {
+                ic_cdk::println!("refund_launch_fee: {:?} {}", code, msg);
+                return;
+            }
+        };
+        let amount = Nat::from(amount);
+        if amount <= ledger_fee {
+            return;
+        }
+        let to = Account {
+            owner: caller,
+            subaccount: None,
+        };
+        if let Err(err) = transfer(ledger, None, to, amount - ledger_fee).await {
+            ic_cdk::println!("refund_launch_fee: {}", err);
+        }
+    }
+}
+
+async fn transfer(
+    ledger: Principal,
+    from_subaccount: Option<Subaccount>,
+    to: Account,
+    amount: impl Into<Nat>,
+) -> Result<Nat, String> {
+    let args = TransferArg {
+        from_subaccount,
+        to,
+        fee: None,
+        created_at_time: None,
+        memo: None,
+        amount: amount.into(),
+    };
+    ic_cdk::call::<_, (Result<Nat, TransferError>,)>(ledger, "icrc1_transfer", (args,))
+        .await
+        .map_err(|(code, msg)| format!("Code: {:?}, Message: {:?}", code, msg))?
+        .0
+        .map_err(|err| format!("{:?}", err))
+}
@@ -0,0 +1,165 @@
+use candid::Principal;
+use ic_cdk_macros::query;
+use icrc7_types::icrc7_types::InitArg;
+use icrc_ledger_types::icrc1::account::Account;
+
+use crate::{
+    payment::deposit_account,
+    state::STATE,
+    types::{
+        CollectionInfo, CollectionMetadata, CollectionPage, CollectionSettings,
+        CollectionUpgradeStatus, Launch, LaunchConfig, LaunchError, LaunchPrice, LaunchTemplate,
+        LaunchUsage, MintJob, MonitorConfig, MonitoringStatus, Rollout, SearchCollectionsArg,
+        TopUp, TopUpPolicy, WasmInfo,
+    },
+};
+
+#[query]
+pub fn get_launch_config() -> LaunchConfig {
+    STATE.with(|s| s.borrow().launch_config())
+}
+
+#[query]
+pub fn get_launch_price(with_asset_canister: Option<bool>) -> LaunchPrice {
+    STATE.with(|s| {
+        s.borrow()
+            .launch_price(with_asset_canister.unwrap_or(false))
+    })
+}
+
+// Checks the arguments of a launch as `mint_collection_canister` does before any payment.
+#[query]
+pub fn validate_launch_args(
+    arg: InitArg,
+    settings: Option<CollectionSettings>,
+) -> Result<(), LaunchError> {
+    STATE.with(|s| {
+        s.borrow()
+            .launch_arg(arg, &settings.unwrap_or_default())
+            .map(|_| ())
+    })
+}
+
+#[query]
+pub fn get_launch_usage(principal: Principal) -> LaunchUsage {
+    STATE.with(|s| s.borrow().launch_usage(&principal))
+}
+
+#[query]
+pub fn get_launch_status(id: u64) -> Option<Launch> {
+    STATE.with(|s| s.borrow().get_launch(id))
+}
+
+#[query]
+pub fn list_launches_by_creator(
+    creator: Principal,
+    prev: Option<u64>,
+    take: Option<u32>,
+) -> Vec<Launch> {
+    STATE.with(|s| s.borrow().list_launches_by_creator(creator, prev, take))
+}
+
+// The account `owner` transfers its launch fee to before an `Icrc1Deposit` launch.
+#[query]
+pub fn get_deposit_account(owner: Principal) -> Account {
+    deposit_account(&owner)
+}
+
+#[query]
+pub fn get_collection(canister_id: Principal) -> Option<CollectionInfo> {
+    STATE.with(|s| s.borrow().get_collection(&canister_id))
+}
+
+#[query]
+pub fn get_mint_job(canister_id: Principal) -> Option<MintJob> {
+    STATE.with(|s| s.borrow().mint_job(&canister_id))
+}
+
+#[query]
+pub fn get_template(name: String) -> Option<LaunchTemplate> {
+    STATE.with(|s| s.borrow().get_template(&name))
+}
+
+#[query]
+pub fn list_templates() -> Vec<LaunchTemplate> {
+    STATE.with(|s| s.borrow().list_templates())
+}
+
+#[query]
+pub fn get_rollout() -> Option<Rollout> {
+    STATE.with(|s| s.borrow().rollout())
+}
+
+#[query]
+pub fn get_upgrade_status(canister_id: Principal) -> Option<CollectionUpgradeStatus> {
+    STATE.with(|s| s.borrow().get_upgrade_status(&canister_id))
+}
+
+#[query]
+pub fn list_upgrade_statuses(
+    prev: Option<Principal>,
+    take: Option<u32>,
+) -> Vec<CollectionUpgradeStatus> {
+    STATE.with(|s| s.borrow().list_upgrade_statuses(prev, take))
+}
+
+#[query]
+pub fn list_wasm_versions() -> Vec<WasmInfo> {
+    STATE.with(|s| s.borrow().list_wasm_versions())
+}
+
+#[query]
+pub fn list_collections(prev: Option<Principal>, take: Option<u32>) -> Vec<CollectionInfo> {
+    STATE.with(|s| s.borrow().list_collections(prev, take))
+}
+
+#[query]
+pub fn list_collections_by_creator(
+    creator: Principal,
+    prev: Option<Principal>,
+    take: Option<u32>,
+) -> Vec<CollectionInfo> {
+    STATE.with(|s| s.borrow().list_collections_by_creator(creator, prev, take))
+}
+
+#[query]
+pub fn get_monitor_config() -> MonitorConfig {
+    STATE.with(|s| s.borrow().monitor_config())
+}
+
+#[query]
+pub fn get_top_up_policy(collection: Principal) -> TopUpPolicy {
+    STATE.with(|s| s.borrow().top_up_policy(&collection))
+}
+
+#[query]
+pub fn list_top_ups(
+    collection: Option<Principal>,
+    prev: Option<u64>,
+    take: Option<u32>,
+) -> Vec<TopUp> {
+    STATE.with(|s| s.borrow().list_top_ups(collection, prev, take))
+}
+
+#[query]
+pub fn get_monitoring_status() -> MonitoringStatus {
+    STATE.with(|s| {
+        let s = s.borrow();
+        MonitoringStatus {
+            launchpad_cycles: ic_cdk::api::canister_balance128(),
+            last_checked_at: s.last_checked_at(),
+            alerts: s.cycles_alerts(),
+        }
+    })
+}
+
+#[query]
+pub fn get_collection_metadata(canister_id: Principal) -> Option<CollectionMetadata> {
+    STATE.with(|s| s.borrow().get_collection_metadata(&canister_id))
+}
+
+// Searches the metadata cache by name or symbol prefix, sorted by creation time or supply.
+#[query]
+pub fn search_collections(arg: SearchCollectionsArg) -> CollectionPage {
+    STATE.with(|s| s.borrow().search_collections(arg))
+}
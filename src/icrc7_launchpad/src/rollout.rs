@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use candid::{Encode, Principal};
+use ic_cdk::api::management_canister::main::{
+    install_code, CanisterInstallMode, InstallCodeArgument,
+};
+use icrc7_types::icrc7_types::{LedgerArg, UpgradeArg};
+
+use crate::{state::STATE, types::UpgradeState};
+
+pub const DEFAULT_ROLLOUT_BATCH_SIZE: u32 = 10;
+pub const MAX_ROLLOUT_BATCH_SIZE: u32 = 50;
+
+pub fn encode_upgrade_arg(upgrade_arg: Option<UpgradeArg>) -> Vec<u8> {
+    Encode!(&Some(LedgerArg::Upgrade(upgrade_arg))).unwrap()
+}
+
+// Upgrades one collection and records the outcome in its upgrade status.
+pub async fn upgrade_collection_canister(
+    canister_id: Principal,
+    version: String,
+    wasm_module: Vec<u8>,
+    arg: Vec<u8>,
+) -> Result<(), String> {
+    STATE.with(|s| {
+        s.borrow_mut().set_upgrade_state(
+            canister_id,
+            version.clone(),
+            UpgradeState::InProgress,
+            ic_cdk::api::time(),
+        )
+    });
+    let result = install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Upgrade(None),
+        canister_id,
+        wasm_module,
+        arg,
+    })
+    .await
+    .map_err(|(code, msg)| format!("Code: {:?}, Message: {:?}", code, msg));
+    let state = match &result {
+        Ok(()) => UpgradeState::Upgraded,
+        Err(err) => UpgradeState::Failed(err.clone()),
+    };
+    STATE.with(|s| {
+        s.borrow_mut()
+            .set_upgrade_state(canister_id, version, state, ic_cdk::api::time())
+    });
+    result
+}
+
+pub fn schedule_rollout_batch() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(run_rollout_batch()));
+}
+
+// Upgrades the next batch of the running rollout and schedules the one after it.
+async fn run_rollout_batch() {
+    let rollout = match STATE.with(|s| s.borrow().rollout()) {
+        Some(rollout) if rollout.running => rollout,
+        _ => return,
+    };
+    let batch = STATE.with(|s| s.borrow().rollout_batch(&rollout));
+    if batch.is_empty() {
+        STATE.with(|s| {
+            let mut s = s.borrow_mut();
+            let mut rollout = rollout;
+            rollout.running = false;
+            rollout.finished_at = Some(ic_cdk::api::time());
+            s.set_rollout(Some(rollout));
+        });
+        return;
+    }
+    let wasm_module = match STATE.with(|s| s.borrow().wasm_module(Some(rollout.version.clone()))) {
+        Ok((_, wasm_module)) => wasm_module,
+        Err(err) => {
+            ic_cdk::println!("run_rollout_batch: {}", err);
+            STATE.with(|s| {
+                let mut s = s.borrow_mut();
+                let mut rollout = rollout;
+                rollout.running = false;
+                s.set_rollout(Some(rollout));
+            });
+            return;
+        }
+    };
+
+    for info in batch {
+        // the rollout may have been paused or replaced while a collection was upgraded
+        let is_current = STATE.with(|s| {
+            s.borrow()
+                .rollout()
+                .is_some_and(|current| current.running && current.started_at == rollout.started_at)
+        });
+        if !is_current {
+            return;
+        }
+
+        // handed over collections can't be upgraded by the launchpad anymore
+        let result = if info.wasm_version == rollout.version || info.is_handed_over() {
+            None
+        } else {
+            Some(
+                upgrade_collection_canister(
+                    info.canister_id,
+                    rollout.version.clone(),
+                    wasm_module.clone(),
+                    rollout.arg.clone(),
+                )
+                .await,
+            )
+        };
+
+        STATE.with(|s| {
+            let mut s = s.borrow_mut();
+            if let Some(mut current) = s.rollout() {
+                if current.started_at != rollout.started_at {
+                    return;
+                }
+                current.cursor = Some(info.canister_id);
+                match result {
+                    None => current.skipped += 1,
+                    Some(Ok(())) => current.upgraded += 1,
+                    Some(Err(_)) => current.failed += 1,
+                }
+                s.set_rollout(Some(current));
+            }
+        });
+    }
+
+    schedule_rollout_batch();
+}
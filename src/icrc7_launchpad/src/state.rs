@@ -0,0 +1,1314 @@
+use std::{cell::RefCell, ops::Bound};
+
+use candid::Principal;
+use ic_stable_structures::{
+    memory_manager::MemoryManager, DefaultMemoryImpl, StableBTreeMap, StableCell,
+};
+use icrc7_types::icrc7_types::InitArg;
+use icrc_ledger_types::icrc1::account::Account;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    memory::{
+        get_canister_cycles_memory, get_collection_metadata_memory, get_collections_memory,
+        get_creator_index_memory, get_launch_config_memory, get_launch_creator_index_memory,
+        get_launch_usage_memory, get_launches_memory, get_mint_jobs_memory, get_mint_queue_memory,
+        get_monitor_config_memory, get_rollout_memory, get_spare_canisters_memory,
+        get_templates_memory, get_top_up_policies_memory, get_top_ups_memory,
+        get_upgrade_status_memory, get_wasm_info_memory, get_wasm_module_memory,
+        get_wasm_upload_memory, Memory,
+    },
+    payment::LaunchFee,
+    templates::{default_templates, MAX_TEMPLATE_NAME_LENGTH},
+    types::{
+        CanisterCycles, CollectionInfo, CollectionMetadata, CollectionPage, CollectionSettings,
+        CollectionSort, CollectionUpgradeStatus, FailedMint, InitialToken, Launch, LaunchConfig,
+        LaunchError, LaunchPrice, LaunchStatus, LaunchTemplate, LaunchUsage, MintJob,
+        MonitorConfig, Rollout, SearchCollectionsArg, TopUp, TopUpPolicy, UpgradeState, WasmInfo,
+        WasmUpload,
+    },
+    update_method::{ICRC7_WASM, ICRC7_WASM_VERSION},
+};
+
+pub struct State {
+    pub collections: StableBTreeMap<Principal, CollectionInfo, Memory>,
+    pub collections_by_creator: StableBTreeMap<(Principal, Principal), (), Memory>,
+    pub wasm_infos: StableBTreeMap<String, WasmInfo, Memory>,
+    pub wasm_modules: StableBTreeMap<String, Vec<u8>, Memory>,
+    pub wasm_uploads: StableBTreeMap<String, WasmUpload, Memory>,
+    pub upgrade_statuses: StableBTreeMap<Principal, CollectionUpgradeStatus, Memory>,
+    pub rollout: StableCell<Option<Rollout>, Memory>,
+    pub launch_config: StableCell<LaunchConfig, Memory>,
+    pub launch_usage: StableBTreeMap<Principal, LaunchUsage, Memory>,
+    pub launches: StableBTreeMap<u64, Launch, Memory>,
+    pub launches_by_creator: StableBTreeMap<(Principal, u64), (), Memory>,
+    pub spare_canisters: StableBTreeMap<Principal, (), Memory>,
+    pub mint_jobs: StableBTreeMap<Principal, MintJob, Memory>,
+    pub mint_queue: StableBTreeMap<(Principal, u64), InitialToken, Memory>,
+    pub templates: StableBTreeMap<String, LaunchTemplate, Memory>,
+    pub monitor_config: StableCell<MonitorConfig, Memory>,
+    pub top_up_policies: StableBTreeMap<Principal, TopUpPolicy, Memory>,
+    pub canister_cycles: StableBTreeMap<Principal, CanisterCycles, Memory>,
+    pub top_ups: StableBTreeMap<u64, TopUp, Memory>,
+    pub collection_metadata: StableBTreeMap<Principal, CollectionMetadata, Memory>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            collections: get_collections_memory(),
+            collections_by_creator: get_creator_index_memory(),
+            wasm_infos: get_wasm_info_memory(),
+            wasm_modules: get_wasm_module_memory(),
+            wasm_uploads: get_wasm_upload_memory(),
+            upgrade_statuses: get_upgrade_status_memory(),
+            rollout: get_rollout_memory(),
+            launch_config: get_launch_config_memory(),
+            launch_usage: get_launch_usage_memory(),
+            launches: get_launches_memory(),
+            launches_by_creator: get_launch_creator_index_memory(),
+            spare_canisters: get_spare_canisters_memory(),
+            mint_jobs: get_mint_jobs_memory(),
+            mint_queue: get_mint_queue_memory(),
+            templates: get_templates_memory(),
+            monitor_config: get_monitor_config_memory(),
+            top_up_policies: get_top_up_policies_memory(),
+            canister_cycles: get_canister_cycles_memory(),
+            top_ups: get_top_ups_memory(),
+            collection_metadata: get_collection_metadata_memory(),
+        }
+    }
+}
+
+impl State {
+    pub const DEFAULT_TAKE_VALUE: u32 = 20;
+    pub const MAX_TAKE_VALUE: u32 = 100;
+    // the oldest top-ups are dropped past this count
+    pub const MAX_TOP_UP_HISTORY: u64 = 10_000;
+
+    pub fn register_collection(&mut self, info: CollectionInfo) {
+        self.collections_by_creator
+            .insert((info.creator, info.canister_id), ());
+        if !self.collection_metadata.contains_key(&info.canister_id) {
+            self.collection_metadata
+                .insert(info.canister_id, CollectionMetadata::from_info(&info));
+        }
+        self.collections.insert(info.canister_id, info);
+    }
+
+    pub fn get_collection(&self, canister_id: &Principal) -> Option<CollectionInfo> {
+        self.collections.get(canister_id)
+    }
+
+    // Collections ordered by canister id, starting after `prev`.
+    pub fn list_collections(
+        &self,
+        prev: Option<Principal>,
+        take: Option<u32>,
+    ) -> Vec<CollectionInfo> {
+        let start = match prev {
+            Some(prev) => Bound::Excluded(prev),
+            None => Bound::Unbounded,
+        };
+        self.collections
+            .range((start, Bound::Unbounded))
+            .take(Self::take(take))
+            .map(|(_, info)| info)
+            .collect()
+    }
+
+    // Collections of `creator` ordered by canister id, starting after `prev`.
+    pub fn list_collections_by_creator(
+        &self,
+        creator: Principal,
+        prev: Option<Principal>,
+        take: Option<u32>,
+    ) -> Vec<CollectionInfo> {
+        let start = match prev {
+            Some(prev) => Bound::Excluded((creator, prev)),
+            None => Bound::Included((creator, Principal::from_slice(&[]))),
+        };
+        self.collections_by_creator
+            .range((start, Bound::Unbounded))
+            .take_while(|((owner, _), _)| *owner == creator)
+            .take(Self::take(take))
+            .filter_map(|((_, canister_id), _)| self.collections.get(&canister_id))
+            .collect()
+    }
+
+    // Starts the upload of `version`, an unfinished upload of the same version is dropped.
+    pub fn start_wasm_upload(&mut self, version: String, sha256: Vec<u8>) -> Result<(), String> {
+        if version.is_empty() || version == ICRC7_WASM_VERSION {
+            return Err(format!("Version {:?} is reserved", version));
+        }
+        if self.wasm_infos.contains_key(&version) {
+            return Err(format!("Version {} already exists", version));
+        }
+        if sha256.len() != 32 {
+            return Err("The sha256 must be 32 bytes".into());
+        }
+        self.wasm_uploads.insert(
+            version,
+            WasmUpload {
+                sha256,
+                wasm: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    // Appends a chunk to the upload of `version`, returns the uploaded size.
+    pub fn upload_wasm_chunk(&mut self, version: String, chunk: Vec<u8>) -> Result<u64, String> {
+        let mut upload = self
+            .wasm_uploads
+            .get(&version)
+            .ok_or_else(|| format!("No upload started for version {}", version))?;
+        upload.wasm.extend_from_slice(&chunk);
+        let size = upload.wasm.len() as u64;
+        self.wasm_uploads.insert(version, upload);
+        Ok(size)
+    }
+
+    // Stores the uploaded wasm of `version` once its sha256 matches the announced one.
+    pub fn commit_wasm_upload(
+        &mut self,
+        version: String,
+        uploaded_by: Principal,
+        uploaded_at: u64,
+    ) -> Result<WasmInfo, String> {
+        let upload = self
+            .wasm_uploads
+            .get(&version)
+            .ok_or_else(|| format!("No upload started for version {}", version))?;
+        let sha256 = Sha256::digest(&upload.wasm).to_vec();
+        if sha256 != upload.sha256 {
+            return Err(format!(
+                "The sha256 of the uploaded wasm doesn't match for version {}",
+                version
+            ));
+        }
+        self.wasm_uploads.remove(&version);
+        let info = WasmInfo {
+            version: version.clone(),
+            sha256,
+            size: upload.wasm.len() as u64,
+            uploaded_at,
+            uploaded_by,
+        };
+        self.wasm_modules.insert(version.clone(), upload.wasm);
+        self.wasm_infos.insert(version, info.clone());
+        Ok(info)
+    }
+
+    pub fn list_wasm_versions(&self) -> Vec<WasmInfo> {
+        self.wasm_infos.iter().map(|(_, info)| info).collect()
+    }
+
+    // The wasm of `version`, the bundled `ICRC7_WASM` when no version is given.
+    pub fn wasm_module(&self, version: Option<String>) -> Result<(String, Vec<u8>), String> {
+        match version {
+            None => Ok((ICRC7_WASM_VERSION.into(), ICRC7_WASM.to_vec())),
+            Some(version) if version == ICRC7_WASM_VERSION => Ok((version, ICRC7_WASM.to_vec())),
+            Some(version) => match self.wasm_modules.get(&version) {
+                Some(wasm) => Ok((version, wasm)),
+                None => Err(format!("Unknown wasm version {}", version)),
+            },
+        }
+    }
+
+    pub fn set_upgrade_state(
+        &mut self,
+        canister_id: Principal,
+        version: String,
+        state: UpgradeState,
+        updated_at: u64,
+    ) {
+        if state == UpgradeState::Upgraded {
+            if let Some(mut info) = self.collections.get(&canister_id) {
+                info.wasm_version = version.clone();
+                self.collections.insert(canister_id, info);
+            }
+        }
+        self.upgrade_statuses.insert(
+            canister_id,
+            CollectionUpgradeStatus {
+                canister_id,
+                version,
+                state,
+                updated_at,
+            },
+        );
+    }
+
+    pub fn get_upgrade_status(&self, canister_id: &Principal) -> Option<CollectionUpgradeStatus> {
+        self.upgrade_statuses.get(canister_id)
+    }
+
+    // Upgrade statuses ordered by canister id, starting after `prev`.
+    pub fn list_upgrade_statuses(
+        &self,
+        prev: Option<Principal>,
+        take: Option<u32>,
+    ) -> Vec<CollectionUpgradeStatus> {
+        let start = match prev {
+            Some(prev) => Bound::Excluded(prev),
+            None => Bound::Unbounded,
+        };
+        self.upgrade_statuses
+            .range((start, Bound::Unbounded))
+            .take(Self::take(take))
+            .map(|(_, status)| status)
+            .collect()
+    }
+
+    pub fn rollout(&self) -> Option<Rollout> {
+        self.rollout.get().clone()
+    }
+
+    pub fn set_rollout(&mut self, rollout: Option<Rollout>) {
+        self.rollout
+            .set(rollout)
+            .expect("failed to save the rollout");
+    }
+
+    // The next collections of the rollout, after its cursor.
+    pub fn rollout_batch(&self, rollout: &Rollout) -> Vec<CollectionInfo> {
+        let start = match rollout.cursor {
+            Some(cursor) => Bound::Excluded(cursor),
+            None => Bound::Unbounded,
+        };
+        self.collections
+            .range((start, Bound::Unbounded))
+            .take(rollout.batch_size as usize)
+            .map(|(_, info)| info)
+            .collect()
+    }
+
+    pub fn launch_config(&self) -> LaunchConfig {
+        self.launch_config.get().clone()
+    }
+
+    pub fn set_launch_config(&mut self, config: LaunchConfig) -> Result<(), String> {
+        if config.payment_ledger.is_some() && config.cycles_per_token == 0 {
+            return Err("cycles_per_token must be set with a payment ledger".into());
+        }
+        if config.max_launches_per_window.is_some() && config.rate_limit_window_seconds == 0 {
+            return Err("rate_limit_window_seconds must be set with a rate limit".into());
+        }
+        self.launch_config
+            .set(config)
+            .expect("failed to save the launch config");
+        Ok(())
+    }
+
+    // The tokens are rounded up so that they always cover the cycles of the launch.
+    pub fn launch_price(&self, with_asset_canister: bool) -> LaunchPrice {
+        let config = self.launch_config();
+        let asset_canister_cycles = if with_asset_canister {
+            config.asset_canister_cycles()
+        } else {
+            0
+        };
+        let cycles = config.collection_cycles + asset_canister_cycles;
+        LaunchPrice {
+            cycles,
+            asset_canister_cycles,
+            payment_ledger: config.payment_ledger,
+            tokens: config
+                .payment_ledger
+                .map(|_| cycles.div_ceil(config.cycles_per_token)),
+        }
+    }
+
+    pub fn launch_usage(&self, principal: &Principal) -> LaunchUsage {
+        self.launch_usage.get(principal).unwrap_or_default()
+    }
+
+    // Counts a launch of `principal` against its quota and rate limit, before it starts.
+    pub fn reserve_launch(&mut self, principal: Principal, now: u64) -> Result<(), LaunchError> {
+        let config = self.launch_config();
+        let mut usage = self.launch_usage(&principal);
+        let window = config
+            .rate_limit_window_seconds
+            .saturating_mul(1_000_000_000);
+        if now.saturating_sub(usage.window_start) >= window {
+            usage.window_start = now;
+            usage.window_count = 0;
+        }
+        if let Some(max) = config.max_launches_per_principal {
+            if usage.total >= max {
+                return Err(LaunchError::QuotaExceeded { max });
+            }
+        }
+        if let Some(max) = config.max_launches_per_window {
+            if usage.window_count >= max {
+                return Err(LaunchError::RateLimited {
+                    max,
+                    window_seconds: config.rate_limit_window_seconds,
+                });
+            }
+        }
+        usage.total += 1;
+        usage.window_count += 1;
+        self.launch_usage.insert(principal, usage);
+        Ok(())
+    }
+
+    // Gives back a reserved launch that didn't happen.
+    pub fn release_launch(&mut self, principal: Principal) {
+        let mut usage = self.launch_usage(&principal);
+        usage.total = usage.total.saturating_sub(1);
+        usage.window_count = usage.window_count.saturating_sub(1);
+        self.launch_usage.insert(principal, usage);
+    }
+
+    // Records a new launch of `creator` waiting for its fee, returns its id.
+    pub fn start_launch(
+        &mut self,
+        creator: Principal,
+        symbol: String,
+        name: String,
+        wasm_version: String,
+        now: u64,
+    ) -> u64 {
+        let id = self.launches.last_key_value().map_or(0, |(id, _)| id + 1);
+        self.launches_by_creator.insert((creator, id), ());
+        self.launches.insert(
+            id,
+            Launch {
+                id,
+                creator,
+                symbol,
+                name,
+                wasm_version,
+                status: LaunchStatus::Paying,
+                started_at: now,
+                updated_at: now,
+                fee: None,
+                asset_canister: None,
+            },
+        );
+        id
+    }
+
+    // Records the fee collected for a launch, so that it can be refunded after an upgrade.
+    pub fn set_launch_fee(&mut self, id: u64, fee: LaunchFee) {
+        if let Some(mut launch) = self.launches.get(&id) {
+            launch.fee = Some(fee);
+            self.launches.insert(id, launch);
+        }
+    }
+
+    pub fn set_launch_asset_canister(&mut self, id: u64, asset_canister: Principal) {
+        if let Some(mut launch) = self.launches.get(&id) {
+            launch.asset_canister = Some(asset_canister);
+            self.launches.insert(id, launch);
+        }
+    }
+
+    pub fn set_launch_status(&mut self, id: u64, status: LaunchStatus, now: u64) {
+        if let Some(mut launch) = self.launches.get(&id) {
+            launch.status = status;
+            launch.updated_at = now;
+            self.launches.insert(id, launch);
+        }
+    }
+
+    // Fails the launches an upgrade interrupted and gives their reservations back, unless
+    // their creator `is_admin`. Returns them as they were, with their fees and canisters.
+    pub fn recover_interrupted_launches(
+        &mut self,
+        is_admin: impl Fn(&Principal) -> bool,
+        now: u64,
+    ) -> Vec<Launch> {
+        let interrupted: Vec<Launch> = self
+            .launches
+            .iter()
+            .map(|(_, launch)| launch)
+            .filter(|launch| {
+                matches!(
+                    launch.status,
+                    LaunchStatus::Paying | LaunchStatus::Creating | LaunchStatus::Installing { .. }
+                )
+            })
+            .collect();
+        for launch in interrupted.iter() {
+            if !is_admin(&launch.creator) {
+                self.release_launch(launch.creator);
+            }
+            self.set_launch_status(
+                launch.id,
+                LaunchStatus::Failed(LaunchError::Interrupted),
+                now,
+            );
+        }
+        interrupted
+    }
+
+    pub fn get_launch(&self, id: u64) -> Option<Launch> {
+        self.launches.get(&id)
+    }
+
+    // Launches of `creator` ordered by id, starting after `prev`.
+    pub fn list_launches_by_creator(
+        &self,
+        creator: Principal,
+        prev: Option<u64>,
+        take: Option<u32>,
+    ) -> Vec<Launch> {
+        let start = match prev {
+            Some(prev) => Bound::Excluded((creator, prev)),
+            None => Bound::Included((creator, 0)),
+        };
+        self.launches_by_creator
+            .range((start, Bound::Unbounded))
+            .take_while(|((owner, _), _)| *owner == creator)
+            .take(Self::take(take))
+            .filter_map(|((_, id), _)| self.launches.get(&id))
+            .collect()
+    }
+
+    pub fn push_spare_canister(&mut self, canister_id: Principal) {
+        self.spare_canisters.insert(canister_id, ());
+    }
+
+    pub fn pop_spare_canister(&mut self) -> Option<Principal> {
+        let (canister_id, _) = self.spare_canisters.first_key_value()?;
+        self.spare_canisters.remove(&canister_id);
+        Some(canister_id)
+    }
+
+    pub fn spare_canister_count(&self) -> u64 {
+        self.spare_canisters.len()
+    }
+
+    // Queues the initial tokens of a collection, checked by `validate_initial_tokens`.
+    pub fn start_mint_job(
+        &mut self,
+        canister_id: Principal,
+        minting_account: Account,
+        tokens: Vec<InitialToken>,
+        now: u64,
+    ) -> MintJob {
+        let job = MintJob {
+            canister_id,
+            minting_account,
+            total: tokens.len() as u64,
+            cursor: 0,
+            minted: 0,
+            failed: vec![],
+            running: true,
+            last_error: None,
+            started_at: now,
+            finished_at: None,
+        };
+        for (index, token) in tokens.into_iter().enumerate() {
+            self.mint_queue.insert((canister_id, index as u64), token);
+        }
+        self.mint_jobs.insert(canister_id, job.clone());
+        job
+    }
+
+    pub fn mint_job(&self, canister_id: &Principal) -> Option<MintJob> {
+        self.mint_jobs.get(canister_id)
+    }
+
+    pub fn set_mint_job(&mut self, job: MintJob) {
+        self.mint_jobs.insert(job.canister_id, job);
+    }
+
+    pub fn running_mint_jobs(&self) -> Vec<Principal> {
+        self.mint_jobs
+            .iter()
+            .filter(|(_, job)| job.running)
+            .map(|(canister_id, _)| canister_id)
+            .collect()
+    }
+
+    // The next tokens of the job, from its cursor.
+    pub fn mint_batch(&self, job: &MintJob, size: u64) -> Vec<(u64, InitialToken)> {
+        self.mint_queue
+            .range((job.canister_id, job.cursor)..(job.canister_id, job.total))
+            .take(size as usize)
+            .map(|((_, index), token)| (index, token))
+            .collect()
+    }
+
+    // Moves the cursor of the job past the token at `index`.
+    pub fn record_mint(
+        &mut self,
+        canister_id: Principal,
+        index: u64,
+        result: Result<(), FailedMint>,
+    ) -> Option<MintJob> {
+        let mut job = self.mint_jobs.get(&canister_id)?;
+        match result {
+            Ok(()) => job.minted += 1,
+            Err(failed) => job.failed.push(failed),
+        }
+        job.cursor = index + 1;
+        self.mint_queue.remove(&(canister_id, index));
+        self.mint_jobs.insert(canister_id, job.clone());
+        Some(job)
+    }
+
+    // `arg` filled from the template of `settings`, or the fields that can't be used.
+    pub fn launch_arg(
+        &self,
+        mut arg: InitArg,
+        settings: &CollectionSettings,
+    ) -> Result<InitArg, LaunchError> {
+        if let Some(name) = &settings.template {
+            let template = self
+                .get_template(name)
+                .ok_or_else(|| LaunchError::UnknownTemplate(name.clone()))?;
+            template.apply(&mut arg);
+        }
+        arg.validate().map_err(LaunchError::InvalidInitArg)?;
+        Ok(arg)
+    }
+
+    pub fn set_template(&mut self, template: LaunchTemplate) -> Result<(), String> {
+        if template.name.is_empty() || template.name.len() > MAX_TEMPLATE_NAME_LENGTH {
+            return Err(format!(
+                "A template name is 1 to {} bytes long",
+                MAX_TEMPLATE_NAME_LENGTH
+            ));
+        }
+        self.templates.insert(template.name.clone(), template);
+        Ok(())
+    }
+
+    pub fn remove_template(&mut self, name: &String) -> Option<LaunchTemplate> {
+        self.templates.remove(name)
+    }
+
+    pub fn get_template(&self, name: &String) -> Option<LaunchTemplate> {
+        self.templates.get(name)
+    }
+
+    pub fn list_templates(&self) -> Vec<LaunchTemplate> {
+        self.templates
+            .iter()
+            .map(|(_, template)| template)
+            .collect()
+    }
+
+    // Adds the default templates to a launchpad without any.
+    pub fn seed_default_templates(&mut self) {
+        if self.templates.is_empty() {
+            for template in default_templates() {
+                self.templates.insert(template.name.clone(), template);
+            }
+        }
+        // the badges of the former `soulbound-badges` can be transferred by their holders
+        if let Some(mut template) = self.templates.remove(&"soulbound-badges".to_string()) {
+            template.name = "revocable-badges".into();
+            if !self.templates.contains_key(&template.name) {
+                self.templates.insert(template.name.clone(), template);
+            }
+        }
+    }
+
+    pub fn set_collection_metadata(&mut self, metadata: CollectionMetadata) {
+        self.collection_metadata
+            .insert(metadata.canister_id, metadata);
+    }
+
+    pub fn get_collection_metadata(&self, canister_id: &Principal) -> Option<CollectionMetadata> {
+        self.collection_metadata.get(canister_id)
+    }
+
+    // The cached collections matching the prefix, sorted and paged. Ties are ordered by
+    // canister id, so that pages don't overlap.
+    pub fn search_collections(&self, arg: SearchCollectionsArg) -> CollectionPage {
+        let mut collections: Vec<CollectionMetadata> = self
+            .collection_metadata
+            .iter()
+            .map(|(_, metadata)| metadata)
+            .filter(|metadata| {
+                arg.prefix
+                    .as_deref()
+                    .is_none_or(|prefix| metadata.matches(prefix))
+            })
+            .collect();
+        match arg.sort_by.unwrap_or_default() {
+            CollectionSort::CreatedAt => {
+                collections.sort_by_key(|metadata| (metadata.created_at, metadata.canister_id))
+            }
+            CollectionSort::TotalSupply => {
+                collections.sort_by_key(|metadata| (metadata.total_supply, metadata.canister_id))
+            }
+        }
+        if arg.descending.unwrap_or(false) {
+            collections.reverse();
+        }
+        let total = collections.len() as u64;
+        let collections = collections
+            .into_iter()
+            .skip(arg.offset.unwrap_or(0) as usize)
+            .take(Self::take(arg.take))
+            .collect();
+        CollectionPage { collections, total }
+    }
+
+    pub fn monitor_config(&self) -> MonitorConfig {
+        self.monitor_config.get().clone()
+    }
+
+    pub fn set_monitor_config(&mut self, config: MonitorConfig) -> Result<(), String> {
+        if config.interval_seconds < 60 {
+            return Err("interval_seconds must be at least 60".into());
+        }
+        self.monitor_config
+            .set(config)
+            .expect("failed to save the monitor config");
+        Ok(())
+    }
+
+    // The policy of `collection`, the default one unless it has its own.
+    pub fn top_up_policy(&self, collection: &Principal) -> TopUpPolicy {
+        self.top_up_policies
+            .get(collection)
+            .unwrap_or_else(|| self.monitor_config().default_policy)
+    }
+
+    pub fn set_top_up_policy(&mut self, collection: Principal, policy: Option<TopUpPolicy>) {
+        match policy {
+            Some(policy) => self.top_up_policies.insert(collection, policy),
+            None => self.top_up_policies.remove(&collection),
+        };
+    }
+
+    pub fn record_canister_cycles(&mut self, cycles: CanisterCycles) {
+        self.canister_cycles.insert(cycles.canister_id, cycles);
+    }
+
+    pub fn get_canister_cycles(&self, canister_id: &Principal) -> Option<CanisterCycles> {
+        self.canister_cycles.get(canister_id)
+    }
+
+    pub fn remove_canister_cycles(&mut self, canister_id: &Principal) {
+        self.canister_cycles.remove(canister_id);
+    }
+
+    // Drops the checks of a collection and of its archives.
+    pub fn forget_canister_cycles(&mut self, collection: &Principal) {
+        let canister_ids: Vec<Principal> = self
+            .canister_cycles
+            .iter()
+            .filter(|(_, cycles)| cycles.collection == *collection)
+            .map(|(canister_id, _)| canister_id)
+            .collect();
+        for canister_id in canister_ids {
+            self.canister_cycles.remove(&canister_id);
+        }
+    }
+
+    pub fn cycles_alerts(&self) -> Vec<CanisterCycles> {
+        self.canister_cycles
+            .iter()
+            .map(|(_, cycles)| cycles)
+            .filter(|cycles| cycles.is_alert())
+            .collect()
+    }
+
+    pub fn last_checked_at(&self) -> Option<u64> {
+        self.canister_cycles
+            .iter()
+            .map(|(_, cycles)| cycles.checked_at)
+            .max()
+    }
+
+    pub fn record_top_up(&mut self, mut top_up: TopUp) -> TopUp {
+        top_up.id = self.top_ups.last_key_value().map_or(0, |(id, _)| id + 1);
+        self.top_ups.insert(top_up.id, top_up.clone());
+        while self.top_ups.len() > Self::MAX_TOP_UP_HISTORY {
+            if let Some((id, _)) = self.top_ups.first_key_value() {
+                self.top_ups.remove(&id);
+            }
+        }
+        top_up
+    }
+
+    // Top-ups ordered by id, starting after `prev`, of `collection` only if given.
+    pub fn list_top_ups(
+        &self,
+        collection: Option<Principal>,
+        prev: Option<u64>,
+        take: Option<u32>,
+    ) -> Vec<TopUp> {
+        let start = match prev {
+            Some(prev) => Bound::Excluded(prev),
+            None => Bound::Unbounded,
+        };
+        self.top_ups
+            .range((start, Bound::Unbounded))
+            .map(|(_, top_up)| top_up)
+            .filter(|top_up| collection.is_none_or(|collection| top_up.collection == collection))
+            .take(Self::take(take))
+            .collect()
+    }
+
+    fn take(take: Option<u32>) -> usize {
+        take.unwrap_or(Self::DEFAULT_TAKE_VALUE)
+            .min(Self::MAX_TAKE_VALUE) as usize
+    }
+}
+
+thread_local! {
+    pub static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+    pub static STATE: RefCell<State> = RefCell::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minting::validate_initial_tokens;
+    use crate::types::MonitoredKind;
+
+    #[test]
+    fn rollouts_resume_after_their_cursor() {
+        let mut state = State::default();
+        for id in 1..=5 {
+            state.register_collection(collection(id, 1));
+        }
+        let mut rollout = Rollout {
+            version: "0.2.0".into(),
+            arg: vec![],
+            batch_size: 2,
+            cursor: None,
+            running: true,
+            upgraded: 0,
+            skipped: 0,
+            failed: 0,
+            started_at: 0,
+            finished_at: None,
+        };
+
+        let mut batches = vec![];
+        loop {
+            let batch = state.rollout_batch(&rollout);
+            if batch.is_empty() {
+                break;
+            }
+            for info in batch.iter() {
+                state.set_upgrade_state(
+                    info.canister_id,
+                    rollout.version.clone(),
+                    UpgradeState::Upgraded,
+                    1,
+                );
+            }
+            rollout.cursor = batch.last().map(|info| info.canister_id);
+            // a launchpad upgrade between two batches keeps the rollout
+            state.set_rollout(Some(rollout.clone()));
+            rollout = State::default().rollout().unwrap();
+            batches.push(batch.len());
+        }
+
+        assert_eq!(batches, vec![2, 2, 1]);
+        let canister_id = Principal::from_slice(&[4; 10]);
+        assert_eq!(
+            state.get_collection(&canister_id).unwrap().wasm_version,
+            "0.2.0"
+        );
+        assert_eq!(
+            state.get_upgrade_status(&canister_id).unwrap().state,
+            UpgradeState::Upgraded
+        );
+        assert_eq!(state.list_upgrade_statuses(None, None).len(), 5);
+    }
+
+    #[test]
+    fn launches_are_rate_limited_and_priced() {
+        let mut state = State::default();
+        let creator = Principal::from_slice(&[1; 29]);
+        let hour = 60 * 60 * 1_000_000_000;
+        state
+            .set_launch_config(LaunchConfig {
+                collection_cycles: 1_000_001,
+                payment_ledger: Some(Principal::from_slice(&[2; 10])),
+                cycles_per_token: 1_000,
+                max_launches_per_principal: Some(3),
+                max_launches_per_window: Some(2),
+                rate_limit_window_seconds: 60 * 60,
+                asset_canister_cycles: Some(1_000),
+                asset_base_url: None,
+            })
+            .unwrap();
+        assert_eq!(state.launch_price(false).tokens, Some(1_001));
+        assert_eq!(state.launch_price(true).tokens, Some(1_002));
+
+        assert!(state.reserve_launch(creator, 0).is_ok());
+        assert!(state.reserve_launch(creator, 1).is_ok());
+        assert!(state.reserve_launch(creator, 2).is_err());
+        state.release_launch(creator);
+        assert!(state.reserve_launch(creator, 3).is_ok());
+        assert!(state.reserve_launch(creator, hour).is_ok());
+        // a new window, but the quota is used up
+        assert!(state.reserve_launch(creator, 2 * hour).is_err());
+        assert_eq!(state.launch_usage(&creator).total, 3);
+    }
+
+    #[test]
+    fn wasm_uploads_are_verified() {
+        let mut state = State::default();
+        let wasm = vec![7u8; 10];
+        let sha256 = Sha256::digest(&wasm).to_vec();
+        let admin = Principal::from_slice(&[1; 29]);
+
+        assert!(state
+            .start_wasm_upload(ICRC7_WASM_VERSION.into(), sha256.clone())
+            .is_err());
+        state.start_wasm_upload("0.2.0".into(), sha256).unwrap();
+        assert_eq!(
+            state.upload_wasm_chunk("0.2.0".into(), wasm[..6].to_vec()),
+            Ok(6)
+        );
+        assert!(state.commit_wasm_upload("0.2.0".into(), admin, 1).is_err());
+        assert_eq!(
+            state.upload_wasm_chunk("0.2.0".into(), wasm[6..].to_vec()),
+            Ok(10)
+        );
+        let info = state.commit_wasm_upload("0.2.0".into(), admin, 1).unwrap();
+
+        assert_eq!(info.size, 10);
+        assert_eq!(state.list_wasm_versions(), vec![info]);
+        assert_eq!(
+            state.wasm_module(Some("0.2.0".into())),
+            Ok(("0.2.0".into(), wasm))
+        );
+        assert_eq!(
+            state.wasm_module(None).map(|(version, _)| version),
+            Ok(ICRC7_WASM_VERSION.to_string())
+        );
+        assert!(state.wasm_module(Some("0.3.0".into())).is_err());
+        assert!(state
+            .start_wasm_upload("0.2.0".into(), vec![0; 32])
+            .is_err());
+    }
+
+    #[test]
+    fn launches_are_tracked() {
+        let mut state = State::default();
+        let creator = Principal::from_slice(&[1; 29]);
+        let other = Principal::from_slice(&[2; 29]);
+        let canister_id = Principal::from_slice(&[3; 10]);
+
+        let first = state.start_launch(creator, "A".into(), "A".into(), "0.1.0".into(), 1);
+        let second = state.start_launch(other, "B".into(), "B".into(), "0.1.0".into(), 2);
+        let third = state.start_launch(creator, "C".into(), "C".into(), "0.1.0".into(), 3);
+        assert_eq!((first, second, third), (0, 1, 2));
+
+        let error = LaunchError::InstallCodeFailed {
+            canister_id,
+            attempts: 3,
+            code: ic_cdk::api::call::RejectionCode::CanisterError,
+            message: "trapped".into(),
+        };
+        state.set_launch_status(first, LaunchStatus::Failed(error.clone()), 4);
+        let launch = state.get_launch(first).unwrap();
+        assert_eq!(launch.status, LaunchStatus::Failed(error));
+        assert_eq!((launch.started_at, launch.updated_at), (1, 4));
+        assert_eq!(
+            state.get_launch(second).unwrap().status,
+            LaunchStatus::Paying
+        );
+
+        let launches = state.list_launches_by_creator(creator, None, None);
+        assert_eq!(
+            launches.iter().map(|launch| launch.id).collect::<Vec<_>>(),
+            vec![first, third]
+        );
+        assert_eq!(
+            state.list_launches_by_creator(creator, Some(first), None)[0].id,
+            third
+        );
+
+        // the launches still running when the launchpad was upgraded are failed
+        state.reserve_launch(creator, 5).unwrap();
+        let fee = LaunchFee::Tokens {
+            ledger: Principal::from_slice(&[4; 10]),
+            amount: 100,
+        };
+        let asset_canister = Principal::from_slice(&[5; 10]);
+        state.set_launch_fee(third, fee.clone());
+        state.set_launch_asset_canister(third, asset_canister);
+        state.set_launch_status(
+            third,
+            LaunchStatus::Installing {
+                canister_id,
+                attempt: 1,
+            },
+            5,
+        );
+        let interrupted = state.recover_interrupted_launches(|principal| *principal == other, 6);
+        assert_eq!(
+            interrupted
+                .iter()
+                .map(|launch| launch.id)
+                .collect::<Vec<_>>(),
+            vec![second, third]
+        );
+        assert_eq!(interrupted[1].fee, Some(fee));
+        assert_eq!(interrupted[1].asset_canister, Some(asset_canister));
+        for id in [second, third] {
+            assert_eq!(
+                state.get_launch(id).unwrap().status,
+                LaunchStatus::Failed(LaunchError::Interrupted)
+            );
+        }
+        assert_eq!(state.launch_usage(&creator).total, 0);
+        assert!(state.recover_interrupted_launches(|_| false, 7).is_empty());
+
+        state.push_spare_canister(canister_id);
+        assert_eq!(state.spare_canister_count(), 1);
+        assert_eq!(state.pop_spare_canister(), Some(canister_id));
+        assert_eq!(state.pop_spare_canister(), None);
+    }
+
+    fn token(token_id: u128) -> InitialToken {
+        InitialToken {
+            to: Account {
+                owner: Principal::from_slice(&[1; 29]),
+                subaccount: None,
+            },
+            token_id,
+            memo: None,
+            token_name: None,
+            token_description: None,
+            token_logo: None,
+        }
+    }
+
+    #[test]
+    fn mint_jobs_resume_at_their_cursor() {
+        let mut state = State::default();
+        let canister_id = Principal::from_slice(&[3; 10]);
+        let minting_account = Account {
+            owner: Principal::from_slice(&[4; 29]),
+            subaccount: None,
+        };
+
+        let mut tokens = vec![token(3), token(1), token(2), token(1)];
+        assert!(validate_initial_tokens(&mut tokens, None).is_err());
+        let mut tokens = vec![token(3), token(1), token(2)];
+        assert!(validate_initial_tokens(&mut tokens, Some(2)).is_err());
+        assert!(validate_initial_tokens(&mut tokens, None).is_ok());
+        assert_eq!(tokens, vec![token(1), token(2), token(3)]);
+
+        let job = state.start_mint_job(canister_id, minting_account, tokens, 1);
+        let batch = state.mint_batch(&job, 2);
+        assert_eq!(batch, vec![(0, token(1)), (1, token(2))]);
+        state.record_mint(canister_id, 0, Ok(()));
+        let failed = FailedMint {
+            token_id: 2,
+            error: icrc7_types::errors::MintError::TokenIdAlreadyExist,
+        };
+        state.record_mint(canister_id, 1, Err(failed));
+
+        // a launchpad upgrade keeps the job
+        let state = State::default();
+        assert_eq!(state.running_mint_jobs(), vec![canister_id]);
+        let job = state.mint_job(&canister_id).unwrap();
+        assert_eq!((job.cursor, job.minted, job.failed.len()), (2, 1, 1));
+        assert_eq!(state.mint_batch(&job, 2), vec![(2, token(3))]);
+    }
+
+    #[test]
+    fn templates_fill_unset_fields() {
+        let mut state = State::default();
+        state.seed_default_templates();
+        let names: Vec<_> = state
+            .list_templates()
+            .into_iter()
+            .map(|template| template.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "game-items",
+                "one-of-one-art",
+                "pfp-10k",
+                "revocable-badges"
+            ]
+        );
+        assert!(state.remove_template(&"game-items".into()).is_some());
+        // templates exist, the removed default stays removed
+        state.seed_default_templates();
+        assert_eq!(state.list_templates().len(), 3);
+        // the preset seeded as `soulbound-badges` is renamed on upgrade
+        let mut badges = state.remove_template(&"revocable-badges".into()).unwrap();
+        badges.name = "soulbound-badges".into();
+        state.set_template(badges).unwrap();
+        state.seed_default_templates();
+        assert!(state.get_template(&"soulbound-badges".into()).is_none());
+        assert!(state.get_template(&"revocable-badges".into()).is_some());
+        assert!(state
+            .set_template(crate::templates::default_templates().remove(0))
+            .is_ok());
+
+        let mut arg = InitArg {
+            minting_account: None,
+            icrc7_symbol: "PFP".into(),
+            icrc7_name: "Profile pictures".into(),
+            icrc7_description: None,
+            icrc7_logo: None,
+            icrc7_asset_base_url: None,
+            icrc7_supply_cap: Some(5_000),
+            icrc7_max_query_batch_size: None,
+            icrc7_max_update_batch_size: None,
+            icrc7_max_take_value: None,
+            icrc7_default_take_value: None,
+            icrc7_max_memo_size: None,
+            icrc7_atomic_batch_transfers: None,
+            tx_window: None,
+            permitted_drift: None,
+            approval_init: None,
+            archive_init: None,
+            burn_mode: None,
+            allow_admin_actions: None,
+        };
+        state
+            .get_template(&"pfp-10k".into())
+            .unwrap()
+            .apply(&mut arg);
+        assert_eq!(arg.icrc7_supply_cap, Some(5_000));
+        assert_eq!(arg.icrc7_max_update_batch_size, Some(100));
+        assert!(arg.archive_init.is_some());
+        assert!(arg.approval_init.is_some());
+    }
+
+    #[test]
+    fn launch_args_are_validated() {
+        let mut state = State::default();
+        state.seed_default_templates();
+        let arg = || InitArg {
+            minting_account: None,
+            icrc7_symbol: " ".into(),
+            icrc7_name: "Items".into(),
+            icrc7_description: None,
+            icrc7_logo: None,
+            icrc7_asset_base_url: None,
+            icrc7_supply_cap: None,
+            icrc7_max_query_batch_size: None,
+            icrc7_max_update_batch_size: None,
+            icrc7_max_take_value: Some(10),
+            icrc7_default_take_value: Some(20),
+            icrc7_max_memo_size: None,
+            icrc7_atomic_batch_transfers: None,
+            tx_window: None,
+            permitted_drift: None,
+            approval_init: None,
+            archive_init: None,
+            burn_mode: None,
+            allow_admin_actions: None,
+        };
+        let fields = |state: &State, settings: CollectionSettings| match state
+            .launch_arg(arg(), &settings)
+        {
+            Err(LaunchError::InvalidInitArg(errors)) => {
+                errors.into_iter().map(|error| error.field).collect()
+            }
+            _ => vec![],
+        };
+
+        assert_eq!(
+            fields(&state, CollectionSettings::default()),
+            vec!["icrc7_symbol", "icrc7_default_take_value"]
+        );
+        // the archive of the template is checked as well, the template keeps the set fields
+        let mut template = crate::templates::default_templates().remove(3);
+        if let Some(archive_init) = template.archive_init.as_mut() {
+            archive_init.archive_cycles = 1;
+            archive_init.settle_to_records = archive_init.max_active_records + 1;
+        }
+        state.set_template(template).unwrap();
+        assert_eq!(
+            fields(
+                &state,
+                CollectionSettings {
+                    template: Some("game-items".into()),
+                    ..Default::default()
+                }
+            ),
+            vec![
+                "icrc7_symbol",
+                "icrc7_default_take_value",
+                "archive_init.archiveCycles",
+                "archive_init.settleToRecords"
+            ]
+        );
+        assert!(matches!(
+            state.launch_arg(
+                arg(),
+                &CollectionSettings {
+                    template: Some("unknown".into()),
+                    ..Default::default()
+                }
+            ),
+            Err(LaunchError::UnknownTemplate(_))
+        ));
+    }
+
+    #[test]
+    fn cycles_are_monitored() {
+        let mut state = State::default();
+        let collection = Principal::from_slice(&[1; 10]);
+        let archive = Principal::from_slice(&[2; 10]);
+        let policy = TopUpPolicy {
+            threshold: 100,
+            amount: 500,
+        };
+        assert_eq!(
+            state.top_up_policy(&collection),
+            MonitorConfig::default().default_policy
+        );
+        state.set_top_up_policy(collection, Some(policy));
+        assert_eq!(state.top_up_policy(&collection), policy);
+        assert!(state
+            .set_monitor_config(MonitorConfig {
+                interval_seconds: 1,
+                ..Default::default()
+            })
+            .is_err());
+
+        let checked = |canister_id, kind, cycles: Option<u128>, checked_at| CanisterCycles {
+            canister_id,
+            collection,
+            kind,
+            cycles,
+            threshold: policy.threshold,
+            error: None,
+            checked_at,
+        };
+        state.record_canister_cycles(checked(collection, MonitoredKind::Collection, Some(150), 1));
+        state.record_canister_cycles(checked(archive, MonitoredKind::Archive, Some(50), 2));
+        let alerts = state.cycles_alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].canister_id, archive);
+        assert_eq!(state.last_checked_at(), Some(2));
+        // a collection handed over without the launchpad isn't monitored anymore
+        state.forget_canister_cycles(&collection);
+        assert!(state.cycles_alerts().is_empty());
+        assert_eq!(state.last_checked_at(), None);
+
+        for at in 0..3 {
+            let top_up = state.record_top_up(TopUp {
+                id: 0,
+                canister_id: if at == 1 { collection } else { archive },
+                collection: if at == 2 { archive } else { collection },
+                amount: policy.amount,
+                cycles: 50,
+                error: None,
+                at,
+            });
+            assert_eq!(top_up.id, at);
+        }
+        let ids = |top_ups: Vec<TopUp>| top_ups.iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(ids(state.list_top_ups(None, None, None)), vec![0, 1, 2]);
+        assert_eq!(
+            ids(state.list_top_ups(Some(collection), None, None)),
+            vec![0, 1]
+        );
+        assert_eq!(ids(state.list_top_ups(None, Some(0), Some(1))), vec![1]);
+    }
+
+    #[test]
+    fn collections_are_searched_and_sorted() {
+        let mut state = State::default();
+        for id in 1..=4 {
+            state.register_collection(collection(id, 1));
+        }
+        for (id, supply, name) in [(1, 30, "Apes"), (2, 10, "apex"), (3, 20, "Birds")] {
+            let canister_id = Principal::from_slice(&[id; 10]);
+            let mut metadata = state.get_collection_metadata(&canister_id).unwrap();
+            metadata.name = name.into();
+            metadata.total_supply = supply;
+            state.set_collection_metadata(metadata);
+        }
+        let ids = |page: CollectionPage| {
+            page.collections
+                .iter()
+                .map(|metadata| metadata.canister_id.as_slice()[0])
+                .collect::<Vec<_>>()
+        };
+
+        // registered collections are listed before their first refresh
+        assert_eq!(
+            ids(state.search_collections(Default::default())),
+            vec![1, 2, 3, 4]
+        );
+        let page = state.search_collections(SearchCollectionsArg {
+            prefix: Some("AP".into()),
+            sort_by: Some(CollectionSort::TotalSupply),
+            ..Default::default()
+        });
+        assert_eq!(page.total, 2);
+        assert_eq!(ids(page), vec![2, 1]);
+        // symbols match too
+        assert_eq!(
+            ids(state.search_collections(SearchCollectionsArg {
+                prefix: Some("c4".into()),
+                ..Default::default()
+            })),
+            vec![4]
+        );
+        assert_eq!(
+            ids(state.search_collections(SearchCollectionsArg {
+                sort_by: Some(CollectionSort::TotalSupply),
+                descending: Some(true),
+                offset: Some(1),
+                take: Some(2),
+                ..Default::default()
+            })),
+            vec![3, 2]
+        );
+    }
+
+    fn collection(id: u8, creator: u8) -> CollectionInfo {
+        CollectionInfo {
+            canister_id: Principal::from_slice(&[id; 10]),
+            creator: Principal::from_slice(&[creator; 29]),
+            symbol: format!("C{}", id),
+            name: format!("Collection {}", id),
+            created_at: id as u64,
+            wasm_version: "0.1.0".into(),
+            asset_canister: None,
+            launchpad_controller: Some(true),
+            pending_handover: None,
+            handover: None,
+        }
+    }
+
+    #[test]
+    fn collections_are_listed_by_page() {
+        let mut state = State::default();
+        for id in 1..=5 {
+            state.register_collection(collection(id, id % 2));
+        }
+
+        assert_eq!(
+            state.get_collection(&Principal::from_slice(&[3; 10])),
+            Some(collection(3, 1))
+        );
+        assert_eq!(state.get_collection(&Principal::from_slice(&[9; 10])), None);
+
+        let first_page = state.list_collections(None, Some(2));
+        assert_eq!(first_page, vec![collection(1, 1), collection(2, 0)]);
+        let next_page = state.list_collections(Some(first_page[1].canister_id), Some(10));
+        assert_eq!(
+            next_page,
+            vec![collection(3, 1), collection(4, 0), collection(5, 1)]
+        );
+
+        let creator = Principal::from_slice(&[1; 29]);
+        let first_page = state.list_collections_by_creator(creator, None, Some(2));
+        assert_eq!(first_page, vec![collection(1, 1), collection(3, 1)]);
+        assert_eq!(
+            state.list_collections_by_creator(creator, Some(first_page[1].canister_id), None),
+            vec![collection(5, 1)]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use icrc7_types::{
+    icrc37_types::InitApprovalsArg,
+    icrc3_types::{IndexType, InitArchiveArg},
+    icrc7_types::BurnMode,
+};
+
+use crate::types::LaunchTemplate;
+
+pub const MAX_TEMPLATE_NAME_LENGTH: usize = 64;
+
+fn template(name: &str, description: &str) -> LaunchTemplate {
+    LaunchTemplate {
+        name: name.into(),
+        description: description.into(),
+        icrc7_supply_cap: None,
+        icrc7_max_query_batch_size: None,
+        icrc7_max_update_batch_size: None,
+        icrc7_max_take_value: None,
+        icrc7_default_take_value: None,
+        icrc7_max_memo_size: None,
+        icrc7_atomic_batch_transfers: None,
+        tx_window: None,
+        permitted_drift: None,
+        approval_init: None,
+        archive_init: None,
+        burn_mode: None,
+        allow_admin_actions: None,
+    }
+}
+
+pub fn archive_init(max_active_records: u128, settle_to_records: u128) -> InitArchiveArg {
+    InitArchiveArg {
+        archive_controllers: None,
+        archive_cycles: 2_000_000_000_000,
+        archive_index_type: IndexType::Stable,
+        max_active_records,
+        max_archive_pages: 62500,
+        max_records_in_archive_instance: 10_000_000,
+        max_records_to_archive: 10_000,
+        settle_to_records,
+    }
+}
+
+// The templates a new launchpad starts with.
+pub fn default_templates() -> Vec<LaunchTemplate> {
+    vec![
+        LaunchTemplate {
+            icrc7_supply_cap: Some(10_000),
+            icrc7_max_query_batch_size: Some(100),
+            icrc7_max_update_batch_size: Some(100),
+            icrc7_max_take_value: Some(1_000),
+            icrc7_default_take_value: Some(100),
+            approval_init: Some(InitApprovalsArg {
+                max_approvals: Some(1_000),
+                max_approvals_per_token_or_collection: Some(10),
+                max_revoke_approvals: Some(100),
+                settle_to_approvals: Some(900),
+                collection_approval_requires_token: Some(true),
+            }),
+            archive_init: Some(archive_init(2_000, 1_000)),
+            ..template(
+                "pfp-10k",
+                "A 10k profile picture collection with approvals for marketplaces and an archive",
+            )
+        },
+        LaunchTemplate {
+            icrc7_max_update_batch_size: Some(100),
+            burn_mode: Some(BurnMode::Tombstone),
+            allow_admin_actions: Some(true),
+            ..template(
+                "revocable-badges",
+                "Badges the minting account revokes with admin_burn or moves back with \
+                 admin_transfer. The ledger doesn't block icrc7_transfer, holders can still \
+                 transfer them",
+            )
+        },
+        LaunchTemplate {
+            icrc7_supply_cap: Some(1),
+            icrc7_max_query_batch_size: Some(10),
+            icrc7_max_update_batch_size: Some(10),
+            icrc7_max_take_value: Some(10),
+            icrc7_default_take_value: Some(10),
+            ..template("one-of-one-art", "A single artwork")
+        },
+        LaunchTemplate {
+            icrc7_max_query_batch_size: Some(500),
+            icrc7_max_update_batch_size: Some(500),
+            icrc7_max_take_value: Some(1_000),
+            icrc7_default_take_value: Some(500),
+            icrc7_max_memo_size: Some(256),
+            icrc7_atomic_batch_transfers: Some(false),
+            allow_admin_actions: Some(true),
+            archive_init: Some(archive_init(10_000, 5_000)),
+            ..template(
+                "game-items",
+                "Game items moved in large batches, with admin actions for the game operator. \
+                 Token metadata is set at mint",
+            )
+        },
+    ]
+}
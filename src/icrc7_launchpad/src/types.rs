@@ -0,0 +1,897 @@
+use candid::{CandidType, Decode, Encode, Nat, Principal};
+use ic_cdk::api::{call::RejectionCode, management_canister::main::CanisterSettings};
+use ic_stable_structures::{storable::Bound, Storable};
+use icrc7_types::{
+    errors::MintError,
+    icrc37_types::InitApprovalsArg,
+    icrc3_types::InitArchiveArg,
+    icrc7_types::{BurnMode, FieldError, InitArg, MintArg},
+};
+use icrc_ledger_types::icrc1::account::{Account, DEFAULT_SUBACCOUNT};
+use serde::Deserialize;
+
+use crate::{launch::MAX_CONTROLLERS, payment::LaunchFee};
+
+// A collection canister launched by the launchpad
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CollectionInfo {
+    pub canister_id: Principal,
+    pub creator: Principal,
+    pub symbol: String,
+    pub name: String,
+    // nanoseconds since the epoch
+    pub created_at: u64,
+    pub wasm_version: String,
+    // the asset canister launched with the collection
+    pub asset_canister: Option<Principal>,
+    // whether the launchpad stayed a controller at launch, unknown for the collections
+    // launched before it was recorded
+    pub launchpad_controller: Option<bool>,
+    // a handover that stopped part way
+    pub pending_handover: Option<PendingHandover>,
+    // the last time the creator took control of the collection
+    pub handover: Option<Handover>,
+}
+
+impl CollectionInfo {
+    // Whether the launchpad gave up its controller position, at launch or in the last
+    // handover.
+    pub fn is_handed_over(&self) -> bool {
+        match &self.handover {
+            Some(handover) => !handover.launchpad_controller,
+            None => self.launchpad_controller == Some(false),
+        }
+    }
+}
+
+impl Storable for CollectionInfo {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// An icrc7 wasm uploaded to the launchpad
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WasmInfo {
+    pub version: String,
+    pub sha256: Vec<u8>,
+    pub size: u64,
+    pub uploaded_at: u64,
+    pub uploaded_by: Principal,
+}
+
+impl Storable for WasmInfo {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A wasm being uploaded in chunks, checked against `sha256` on commit
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct WasmUpload {
+    pub sha256: Vec<u8>,
+    pub wasm: Vec<u8>,
+}
+
+impl Storable for WasmUpload {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum UpgradeState {
+    InProgress,
+    Upgraded,
+    Failed(String),
+}
+
+// The last upgrade of a collection
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CollectionUpgradeStatus {
+    pub canister_id: Principal,
+    pub version: String,
+    pub state: UpgradeState,
+    pub updated_at: u64,
+}
+
+impl Storable for CollectionUpgradeStatus {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Upgrades every registered collection to `version`, `batch_size` collections at a time
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Rollout {
+    pub version: String,
+    // the candid encoded upgrade argument of the collections
+    pub arg: Vec<u8>,
+    pub batch_size: u32,
+    // the last collection handled, the rollout resumes after it
+    pub cursor: Option<Principal>,
+    pub running: bool,
+    pub upgraded: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+impl Storable for Rollout {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// How the creator pays for a launch
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LaunchPayment {
+    // cycles attached to the call
+    Cycles,
+    // tokens the creator approved to the launchpad on the payment ledger
+    Icrc2Approval,
+    // tokens the creator transferred to their deposit account of the launchpad
+    Icrc1Deposit,
+}
+
+// Launch settings, editable by the controllers of the launchpad
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LaunchConfig {
+    // cycles given to every new collection, and the price of a launch
+    pub collection_cycles: u128,
+    // the ICRC-1/ICRC-2 ledger accepted as payment, None disables token payments
+    pub payment_ledger: Option<Principal>,
+    // cycles a smallest unit of the payment token is worth
+    pub cycles_per_token: u128,
+    // launches allowed per principal in total, None for no quota
+    pub max_launches_per_principal: Option<u32>,
+    // launches allowed per principal in every window, None for no rate limit
+    pub max_launches_per_window: Option<u32>,
+    pub rate_limit_window_seconds: u64,
+    // cycles given to an asset canister, `DEFAULT_ASSET_CANISTER_CYCLES` when None
+    pub asset_canister_cycles: Option<u128>,
+    // the asset base URL given to the collections, `{canister_id}` is replaced by the id
+    // of their asset canister. `DEFAULT_ASSET_BASE_URL` when None
+    pub asset_base_url: Option<String>,
+}
+
+pub const DEFAULT_ASSET_CANISTER_CYCLES: u128 = 2_000_000_000_000;
+pub const DEFAULT_ASSET_BASE_URL: &str = "https://{canister_id}.raw.icp0.io";
+
+impl LaunchConfig {
+    pub fn asset_canister_cycles(&self) -> u128 {
+        self.asset_canister_cycles
+            .unwrap_or(DEFAULT_ASSET_CANISTER_CYCLES)
+    }
+
+    pub fn asset_base_url(&self, asset_canister: &Principal) -> String {
+        self.asset_base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_ASSET_BASE_URL)
+            .replace("{canister_id}", &asset_canister.to_text())
+    }
+}
+
+impl Default for LaunchConfig {
+    fn default() -> Self {
+        Self {
+            collection_cycles: 10_000_000_000_000,
+            payment_ledger: None,
+            cycles_per_token: 0,
+            max_launches_per_principal: None,
+            max_launches_per_window: Some(5),
+            rate_limit_window_seconds: 24 * 60 * 60,
+            asset_canister_cycles: None,
+            asset_base_url: None,
+        }
+    }
+}
+
+impl Storable for LaunchConfig {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LaunchPrice {
+    // the cycles of the collection and of its asset canister if any
+    pub cycles: u128,
+    pub asset_canister_cycles: u128,
+    pub payment_ledger: Option<Principal>,
+    // the price in smallest token units, the ledger fee comes on top
+    pub tokens: Option<u128>,
+}
+
+// The launches of a principal, counted for its quota and rate limit
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LaunchUsage {
+    pub total: u32,
+    pub window_start: u64,
+    pub window_count: u32,
+}
+
+impl Storable for LaunchUsage {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// The IC default, 30 days
+const DEFAULT_FREEZING_THRESHOLD: u64 = 30 * 24 * 60 * 60;
+
+// Launch options of a collection, its creator is always a controller
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CollectionSettings {
+    // controllers besides the creator
+    pub controllers: Option<Vec<Principal>>,
+    // in seconds
+    pub freezing_threshold: Option<u64>,
+    // in bytes, 0 for best-effort memory
+    pub memory_allocation: Option<u64>,
+    // false removes the launchpad from the controllers once the collection is installed,
+    // it can't be upgraded by the launchpad anymore
+    pub launchpad_controller: Option<bool>,
+    // true launches an asset canister with the same controllers next to the collection
+    pub asset_canister: Option<bool>,
+    // the name of the `LaunchTemplate` the unset fields of the `InitArg` are taken from
+    pub template: Option<String>,
+}
+
+impl CollectionSettings {
+    pub fn with_asset_canister(&self) -> bool {
+        self.asset_canister.unwrap_or(false)
+    }
+
+    pub fn keeps_launchpad_controller(&self) -> bool {
+        self.launchpad_controller.unwrap_or(true)
+    }
+
+    // The controllers of the installed collection.
+    pub fn controllers(&self, launchpad: Principal, creator: Principal) -> Vec<Principal> {
+        let mut controllers = vec![creator];
+        if self.keeps_launchpad_controller() {
+            controllers.insert(0, launchpad);
+        }
+        for controller in self.controllers.iter().flatten() {
+            if !controllers.contains(controller) {
+                controllers.push(*controller);
+            }
+        }
+        controllers
+    }
+
+    // The settings the collection is created with, the launchpad controls it until it is
+    // installed.
+    pub fn canister_settings(&self, launchpad: Principal, creator: Principal) -> CanisterSettings {
+        let mut controllers = self.controllers(launchpad, creator);
+        if !controllers.contains(&launchpad) {
+            controllers.insert(0, launchpad);
+        }
+        CanisterSettings {
+            controllers: Some(controllers),
+            compute_allocation: None,
+            memory_allocation: Some(Nat::from(self.memory_allocation.unwrap_or(0))),
+            freezing_threshold: Some(Nat::from(
+                self.freezing_threshold
+                    .unwrap_or(DEFAULT_FREEZING_THRESHOLD),
+            )),
+            reserved_cycles_limit: None,
+        }
+    }
+}
+
+// Named defaults for the `InitArg` of a kind of collection, editable by the controllers
+// of the launchpad
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LaunchTemplate {
+    pub name: String,
+    pub description: String,
+    pub icrc7_supply_cap: Option<u128>,
+    pub icrc7_max_query_batch_size: Option<u16>,
+    pub icrc7_max_update_batch_size: Option<u16>,
+    pub icrc7_max_take_value: Option<u128>,
+    pub icrc7_default_take_value: Option<u128>,
+    pub icrc7_max_memo_size: Option<u32>,
+    pub icrc7_atomic_batch_transfers: Option<bool>,
+    pub tx_window: Option<u64>,
+    pub permitted_drift: Option<u64>,
+    pub approval_init: Option<InitApprovalsArg>,
+    pub archive_init: Option<InitArchiveArg>,
+    pub burn_mode: Option<BurnMode>,
+    pub allow_admin_actions: Option<bool>,
+}
+
+impl LaunchTemplate {
+    // Fills the fields `arg` leaves unset with the defaults of the template.
+    pub fn apply(&self, arg: &mut InitArg) {
+        arg.icrc7_supply_cap = arg.icrc7_supply_cap.or(self.icrc7_supply_cap);
+        arg.icrc7_max_query_batch_size = arg
+            .icrc7_max_query_batch_size
+            .or(self.icrc7_max_query_batch_size);
+        arg.icrc7_max_update_batch_size = arg
+            .icrc7_max_update_batch_size
+            .or(self.icrc7_max_update_batch_size);
+        arg.icrc7_max_take_value = arg.icrc7_max_take_value.or(self.icrc7_max_take_value);
+        arg.icrc7_default_take_value = arg
+            .icrc7_default_take_value
+            .or(self.icrc7_default_take_value);
+        arg.icrc7_max_memo_size = arg.icrc7_max_memo_size.or(self.icrc7_max_memo_size);
+        arg.icrc7_atomic_batch_transfers = arg
+            .icrc7_atomic_batch_transfers
+            .or(self.icrc7_atomic_batch_transfers);
+        arg.tx_window = arg.tx_window.or(self.tx_window);
+        arg.permitted_drift = arg.permitted_drift.or(self.permitted_drift);
+        if arg.approval_init.is_none() {
+            arg.approval_init = self.approval_init.clone();
+        }
+        if arg.archive_init.is_none() {
+            arg.archive_init = self.archive_init.clone();
+        }
+        arg.burn_mode = arg.burn_mode.or(self.burn_mode);
+        arg.allow_admin_actions = arg.allow_admin_actions.or(self.allow_admin_actions);
+    }
+}
+
+impl Storable for LaunchTemplate {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// The new owners of a collection, set by its creator
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HandoverArg {
+    // the controllers of the collection, its asset canister and its archives
+    pub controllers: Vec<Principal>,
+    // false removes the launchpad from the controllers, it can't upgrade the collection anymore
+    pub keep_launchpad: bool,
+    // passed to `set_minting_authority` of the ledger, unchanged when None
+    pub minting_account: Option<Account>,
+}
+
+impl HandoverArg {
+    // Sorts the controllers with the launchpad last when it stays, and gives the minting
+    // account the default subaccount, as the ledger's `account_transformer` does.
+    pub fn normalize(mut self, launchpad: Principal) -> Result<Self, String> {
+        self.controllers
+            .retain(|controller| *controller != launchpad);
+        self.controllers.sort();
+        self.controllers.dedup();
+        if self.controllers.is_empty() {
+            return Err("At least one controller besides the launchpad".into());
+        }
+        if self.keep_launchpad {
+            self.controllers.push(launchpad);
+        }
+        // archives get the ledger too
+        if self.controllers.len() + 1 > MAX_CONTROLLERS {
+            return Err(format!("At most {} controllers", MAX_CONTROLLERS - 1));
+        }
+        self.minting_account = self.minting_account.map(|account| Account {
+            owner: account.owner,
+            subaccount: Some(account.subaccount.unwrap_or(*DEFAULT_SUBACCOUNT)),
+        });
+        Ok(self)
+    }
+}
+
+// The steps of a handover already done, a retry with the same argument skips them
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingHandover {
+    pub arg: HandoverArg,
+    pub minting_authority_set: bool,
+    // the canisters whose controllers are set
+    pub done: Vec<Principal>,
+    pub by: Principal,
+    pub started_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Handover {
+    pub controllers: Vec<Principal>,
+    pub launchpad_controller: bool,
+    pub minting_account: Option<Account>,
+    pub by: Principal,
+    pub at: u64,
+}
+
+// Why a launch failed
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum LaunchError {
+    AnonymousCaller,
+    UnknownWasmVersion(String),
+    UnknownTemplate(String),
+    InvalidInitArg(Vec<FieldError>),
+    InvalidInitialTokens(String),
+    QuotaExceeded {
+        max: u32,
+    },
+    RateLimited {
+        max: u32,
+        window_seconds: u64,
+    },
+    PaymentFailed(String),
+    CreateCanisterFailed {
+        code: RejectionCode,
+        message: String,
+    },
+    AssetInstallFailed {
+        canister_id: Principal,
+        code: RejectionCode,
+        message: String,
+    },
+    // the empty canister is kept by the launchpad for a later launch, or deleted
+    InstallCodeFailed {
+        canister_id: Principal,
+        attempts: u32,
+        code: RejectionCode,
+        message: String,
+    },
+    // the launchpad was upgraded while the launch was running
+    Interrupted,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum LaunchStatus {
+    Paying,
+    Creating,
+    Installing {
+        canister_id: Principal,
+        attempt: u32,
+    },
+    Installed {
+        canister_id: Principal,
+    },
+    Failed(LaunchError),
+}
+
+// A launch started by `mint_collection_canister`, kept once it is over
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Launch {
+    pub id: u64,
+    pub creator: Principal,
+    pub symbol: String,
+    pub name: String,
+    pub wasm_version: String,
+    pub status: LaunchStatus,
+    pub started_at: u64,
+    pub updated_at: u64,
+    // the fee collected from the creator, refunded if the launch fails
+    pub fee: Option<LaunchFee>,
+    // the asset canister acquired for the collection
+    pub asset_canister: Option<Principal>,
+}
+
+impl Storable for Launch {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A token minted into a collection right after its launch
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct InitialToken {
+    pub to: Account,
+    pub token_id: u128,
+    pub memo: Option<Vec<u8>>,
+    pub token_name: Option<String>,
+    pub token_description: Option<String>,
+    pub token_logo: Option<String>,
+}
+
+impl From<InitialToken> for MintArg {
+    fn from(token: InitialToken) -> Self {
+        Self {
+            from_subaccount: None,
+            to: token.to,
+            token_id: token.token_id,
+            memo: token.memo,
+            token_name: token.token_name,
+            token_description: token.token_description,
+            token_logo: token.token_logo,
+        }
+    }
+}
+
+impl Storable for InitialToken {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FailedMint {
+    pub token_id: u128,
+    pub error: MintError,
+}
+
+// The initial tokens of a collection, minted by the launchpad in batches. The launchpad is
+// the minting account of the collection until they are all minted.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MintJob {
+    pub canister_id: Principal,
+    // the minting account of the collection once the job is finished
+    pub minting_account: Account,
+    pub total: u64,
+    // the index of the next token to mint
+    pub cursor: u64,
+    pub minted: u64,
+    pub failed: Vec<FailedMint>,
+    pub running: bool,
+    // why the job stopped, it resumes at its cursor
+    pub last_error: Option<String>,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+impl Storable for MintJob {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Cycles monitoring of the collections and their archives, editable by the controllers of
+// the launchpad
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MonitorConfig {
+    pub enabled: bool,
+    pub interval_seconds: u64,
+    // the policy of the collections without their own
+    pub default_policy: TopUpPolicy,
+    // cycles the launchpad keeps for itself, top-ups never go below it
+    pub reserve_cycles: u128,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_seconds: 6 * 60 * 60,
+            default_policy: TopUpPolicy {
+                threshold: 1_000_000_000_000,
+                amount: 2_000_000_000_000,
+            },
+            reserve_cycles: 5_000_000_000_000,
+        }
+    }
+}
+
+impl Storable for MonitorConfig {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A canister below `threshold` cycles gets `amount` cycles from the launchpad
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TopUpPolicy {
+    pub threshold: u128,
+    pub amount: u128,
+}
+
+impl Storable for TopUpPolicy {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MonitoredKind {
+    Collection,
+    Archive,
+}
+
+// The last check of a collection or of one of its archives
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CanisterCycles {
+    pub canister_id: Principal,
+    pub collection: Principal,
+    pub kind: MonitoredKind,
+    // None when the status couldn't be read
+    pub cycles: Option<u128>,
+    pub threshold: u128,
+    pub error: Option<String>,
+    pub checked_at: u64,
+}
+
+impl CanisterCycles {
+    pub fn is_alert(&self) -> bool {
+        self.cycles.is_none_or(|cycles| cycles < self.threshold)
+    }
+}
+
+impl Storable for CanisterCycles {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TopUp {
+    pub id: u64,
+    pub canister_id: Principal,
+    pub collection: Principal,
+    pub amount: u128,
+    // the balance of the canister before the top-up
+    pub cycles: u128,
+    pub error: Option<String>,
+    pub at: u64,
+}
+
+impl Storable for TopUp {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MonitoringStatus {
+    pub launchpad_cycles: u128,
+    pub last_checked_at: Option<u64>,
+    // canisters below their threshold or whose status couldn't be read
+    pub alerts: Vec<CanisterCycles>,
+}
+
+// The metadata of a collection as last read from its ledger
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CollectionMetadata {
+    pub canister_id: Principal,
+    pub name: String,
+    pub symbol: String,
+    pub logo: Option<String>,
+    pub total_supply: u128,
+    pub supply_cap: Option<u128>,
+    // the names of the standards announced through ICRC-10
+    pub standards: Vec<String>,
+    // the creation time of the collection, copied from the registry
+    pub created_at: u64,
+    pub refreshed_at: u64,
+    // why the last refresh failed, the fields keep the values of the one before
+    pub error: Option<String>,
+}
+
+impl CollectionMetadata {
+    // The metadata known at launch, until the first refresh.
+    pub fn from_info(info: &CollectionInfo) -> Self {
+        Self {
+            canister_id: info.canister_id,
+            name: info.name.clone(),
+            symbol: info.symbol.clone(),
+            logo: None,
+            total_supply: 0,
+            supply_cap: None,
+            standards: vec![],
+            created_at: info.created_at,
+            refreshed_at: 0,
+            error: None,
+        }
+    }
+
+    // Whether the name or the symbol starts with `prefix`, ignoring case.
+    pub fn matches(&self, prefix: &str) -> bool {
+        let prefix = prefix.to_lowercase();
+        self.name.to_lowercase().starts_with(&prefix)
+            || self.symbol.to_lowercase().starts_with(&prefix)
+    }
+}
+
+impl Storable for CollectionMetadata {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum CollectionSort {
+    #[default]
+    CreatedAt,
+    TotalSupply,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct SearchCollectionsArg {
+    // a prefix of the name or of the symbol, ignoring case
+    pub prefix: Option<String>,
+    pub sort_by: Option<CollectionSort>,
+    pub descending: Option<bool>,
+    // the number of matching collections to skip
+    pub offset: Option<u64>,
+    pub take: Option<u32>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CollectionPage {
+    pub collections: Vec<CollectionMetadata>,
+    // the number of matching collections
+    pub total: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collection_controllers() {
+        let launchpad = Principal::from_slice(&[1; 10]);
+        let creator = Principal::from_slice(&[2; 29]);
+        let sale = Principal::from_slice(&[3; 10]);
+
+        let settings = CollectionSettings::default();
+        assert_eq!(
+            settings.controllers(launchpad, creator),
+            vec![launchpad, creator]
+        );
+
+        let settings = CollectionSettings {
+            controllers: Some(vec![sale, creator]),
+            launchpad_controller: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.controllers(launchpad, creator),
+            vec![creator, sale]
+        );
+        // the launchpad installs the collection before leaving it
+        assert_eq!(
+            settings.canister_settings(launchpad, creator).controllers,
+            Some(vec![launchpad, creator, sale])
+        );
+    }
+
+    #[test]
+    fn collections_left_at_launch_are_handed_over() {
+        let mut info = CollectionInfo {
+            canister_id: Principal::from_slice(&[1; 10]),
+            creator: Principal::from_slice(&[2; 29]),
+            symbol: "C".into(),
+            name: "Collection".into(),
+            created_at: 0,
+            wasm_version: "0.1.0".into(),
+            asset_canister: None,
+            launchpad_controller: None,
+            pending_handover: None,
+            handover: None,
+        };
+        assert!(!info.is_handed_over());
+        info.launchpad_controller = Some(false);
+        assert!(info.is_handed_over());
+        // a later handover decides
+        info.handover = Some(Handover {
+            controllers: vec![info.creator],
+            launchpad_controller: true,
+            minting_account: None,
+            by: info.creator,
+            at: 1,
+        });
+        assert!(!info.is_handed_over());
+    }
+
+    #[test]
+    fn handover_args_are_normalized() {
+        let launchpad = Principal::from_slice(&[1; 10]);
+        let creator = Principal::from_slice(&[2; 29]);
+        let sale = Principal::from_slice(&[3; 29]);
+        let arg = HandoverArg {
+            controllers: vec![sale, launchpad, creator, sale],
+            keep_launchpad: true,
+            minting_account: Some(Account {
+                owner: sale,
+                subaccount: None,
+            }),
+        };
+        let arg = arg.normalize(launchpad).unwrap();
+        assert_eq!(arg.controllers, vec![creator, sale, launchpad]);
+        assert_eq!(
+            arg.minting_account,
+            Some(Account {
+                owner: sale,
+                subaccount: Some([0; 32]),
+            })
+        );
+
+        let arg = HandoverArg {
+            controllers: vec![launchpad],
+            keep_launchpad: true,
+            minting_account: None,
+        };
+        assert!(arg.normalize(launchpad).is_err());
+    }
+}
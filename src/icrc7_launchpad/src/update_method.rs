@@ -1,116 +1,501 @@
-use candid::{CandidType, Encode, Principal};
-use ic_cdk::api::management_canister::{
-    main::{create_canister, install_code, CreateCanisterArgument, InstallCodeArgument},
-    provisional::CanisterSettings,
-};
+use candid::{Encode, Principal};
 use ic_cdk_macros::update;
-use icrc7_types::icrc37_types::InitApprovalsArg;
-use icrc7_types::icrc3_types::InitArchiveArg;
+use icrc7_types::icrc7_types::{InitArg, LedgerArg, UpgradeArg};
 use icrc_ledger_types::icrc1::account::Account;
-use serde::Deserialize;
+
+use crate::{
+    discovery::{refresh_all_metadata, schedule_collection_refresh},
+    guards::admin_guard,
+    launch::{
+        acquire_canister, hand_over_collection, install_assets, install_collection, leave_canister,
+        reclaim_canister,
+    },
+    minting::{launchpad_minting_account, schedule_mint_batch, validate_initial_tokens},
+    monitoring::{run_monitoring, schedule_monitoring},
+    payment::{accept_launch_fee, collect_launch_fee, refund_launch_fee, LaunchFee},
+    rollout::{
+        encode_upgrade_arg, schedule_rollout_batch, upgrade_collection_canister,
+        DEFAULT_ROLLOUT_BATCH_SIZE, MAX_ROLLOUT_BATCH_SIZE,
+    },
+    state::STATE,
+    templates::archive_init,
+    types::{
+        CollectionInfo, CollectionSettings, HandoverArg, InitialToken, Launch, LaunchConfig,
+        LaunchError, LaunchPayment, LaunchStatus, LaunchTemplate, MintJob, MonitorConfig, Rollout,
+        TopUpPolicy, WasmInfo,
+    },
+};
 
 pub const ICRC7_WASM: &[u8] = std::include_bytes!("./../../../wasm/icrc7.wasm.gz");
+// The version of the icrc7 crate `ICRC7_WASM` was built from.
+pub const ICRC7_WASM_VERSION: &str = "0.1.0";
+pub const ASSETS_WASM: &[u8] = std::include_bytes!("./../../../wasm/ic_canister_assets.wasm.gz");
+// Built from `src/icrc7_refund` by `scripts/refund_wasm.sh`.
+pub const REFUND_WASM: &[u8] = std::include_bytes!("./../../../wasm/icrc7_refund.wasm.gz");
 
-#[derive(CandidType, Deserialize)]
-pub struct InitArg {
-    pub minting_account: Option<Account>,
-    pub icrc7_symbol: String,
-    pub icrc7_name: String,
-    pub icrc7_description: Option<String>,
-    pub icrc7_logo: Option<String>,
-    pub icrc7_supply_cap: Option<u128>,
-    pub icrc7_max_query_batch_size: Option<u16>,
-    pub icrc7_max_update_batch_size: Option<u16>,
-    pub icrc7_max_take_value: Option<u128>,
-    pub icrc7_default_take_value: Option<u128>,
-    pub icrc7_max_memo_size: Option<u32>,
-    pub icrc7_atomic_batch_transfers: Option<bool>,
-    pub tx_window: Option<u64>,
-    pub permitted_drift: Option<u64>,
-    pub approval_init: Option<InitApprovalsArg>,
-    pub archive_init: Option<InitArchiveArg>,
-}
-
-#[derive(CandidType, Deserialize)]
-pub struct Arg {
-    pub icrc7_symbol: String,
-    pub icrc7_name: String,
-    pub icrc7_description: Option<String>,
-    pub icrc7_logo: Option<String>,
-    pub icrc7_supply_cap: Option<u128>,
-    pub icrc7_max_query_batch_size: Option<u16>,
-    pub icrc7_max_update_batch_size: Option<u16>,
-    pub icrc7_max_take_value: Option<u128>,
-    pub icrc7_default_take_value: Option<u128>,
-    pub icrc7_max_memo_size: Option<u32>,
-    pub icrc7_atomic_batch_transfers: Option<bool>,
-    pub tx_window: Option<u64>,
-    pub permitted_drift: Option<u64>,
-    pub approval_init: Option<InitApprovalsArg>,
-    pub archive_init: Option<InitArchiveArg>,
-}
-
-impl From<(Account, Arg)> for InitArg {
-    fn from((account, arg): (Account, Arg)) -> Self {
-        Self {
-            minting_account: Some(account),
-            icrc7_symbol: arg.icrc7_symbol,
-            icrc7_name: arg.icrc7_name,
-            icrc7_description: arg.icrc7_description,
-            icrc7_logo: arg.icrc7_logo,
-            icrc7_supply_cap: arg.icrc7_supply_cap,
-            icrc7_max_query_batch_size: arg.icrc7_max_query_batch_size,
-            icrc7_max_update_batch_size: arg.icrc7_max_update_batch_size,
-            icrc7_max_take_value: arg.icrc7_max_take_value,
-            icrc7_default_take_value: arg.icrc7_default_take_value,
-            icrc7_max_memo_size: arg.icrc7_max_memo_size,
-            icrc7_atomic_batch_transfers: arg.icrc7_atomic_batch_transfers,
-            tx_window: arg.tx_window,
-            permitted_drift: arg.permitted_drift,
-            approval_init: arg.approval_init,
-            archive_init: arg.archive_init,
-        }
-    }
+// Launches a collection paid with `payment`, attached cycles by default. The controllers
+// of the launchpad launch for free and without limits. The launch is tracked under the
+// id listed by `list_launches_by_creator`.
+//
+// `arg` is passed to the ledger as is, its minting account defaults to the caller.
+#[update]
+async fn mint_collection_canister(
+    arg: InitArg,
+    wasm_version: Option<String>,
+    payment: Option<LaunchPayment>,
+    settings: Option<CollectionSettings>,
+) -> Result<Principal, LaunchError> {
+    launch_collection(arg, wasm_version, payment, settings, vec![]).await
 }
 
+// Launches a collection like `mint_collection_canister`, then mints `tokens` into it in
+// batches, reported by `get_mint_job`.
 #[update]
-async fn mint_collection_canister(arg: Arg) -> Result<Principal, String> {
+async fn launch_collection_with_tokens(
+    arg: InitArg,
+    wasm_version: Option<String>,
+    payment: Option<LaunchPayment>,
+    settings: Option<CollectionSettings>,
+    tokens: Vec<InitialToken>,
+) -> Result<Principal, LaunchError> {
+    launch_collection(arg, wasm_version, payment, settings, tokens).await
+}
+
+async fn launch_collection(
+    arg: InitArg,
+    wasm_version: Option<String>,
+    payment: Option<LaunchPayment>,
+    settings: Option<CollectionSettings>,
+    mut tokens: Vec<InitialToken>,
+) -> Result<Principal, LaunchError> {
     let caller = ic_cdk::caller();
     if caller == Principal::anonymous() {
-        return Err("Anonymous Caller".into());
+        return Err(LaunchError::AnonymousCaller);
     }
-    let account = Account {
-        owner: caller.clone(),
-        subaccount: None,
+    let settings = settings.unwrap_or_default();
+    let arg = STATE.with(|s| s.borrow().launch_arg(arg, &settings))?;
+    validate_initial_tokens(&mut tokens, arg.icrc7_supply_cap)?;
+    let (wasm_version, wasm_module) = STATE
+        .with(|s| s.borrow().wasm_module(wasm_version.clone()))
+        .map_err(|_| LaunchError::UnknownWasmVersion(wasm_version.unwrap_or_default()))?;
+    let is_admin = ic_cdk::api::is_controller(&caller);
+    if !is_admin {
+        STATE.with(|s| s.borrow_mut().reserve_launch(caller, ic_cdk::api::time()))?;
+    }
+    let launch_id = STATE.with(|s| {
+        s.borrow_mut().start_launch(
+            caller,
+            arg.icrc7_symbol.clone(),
+            arg.icrc7_name.clone(),
+            wasm_version.clone(),
+            ic_cdk::api::time(),
+        )
+    });
+    let job = LaunchJob {
+        id: launch_id,
+        creator: caller,
+        is_admin,
+        wasm_version,
+        wasm_module,
+        settings,
+        tokens,
     };
-    let principal = match create_canister(
-        CreateCanisterArgument {
-            settings: Some(CanisterSettings {
-                controllers: Some(vec![ic_cdk::id(), caller.clone()]),
-                compute_allocation: None,
-                memory_allocation: None,
-                freezing_threshold: None,
-                reserved_cycles_limit: None,
-            }),
+    let result = run_launch(job, arg, payment).await;
+    let status = match &result {
+        Ok(canister_id) => LaunchStatus::Installed {
+            canister_id: *canister_id,
         },
-        10_000_000_000_000,
+        Err(err) => LaunchStatus::Failed(err.clone()),
+    };
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        if result.is_err() && !is_admin {
+            s.release_launch(caller);
+        }
+        s.set_launch_status(launch_id, status, ic_cdk::api::time());
+    });
+    result
+}
+
+struct LaunchJob {
+    id: u64,
+    creator: Principal,
+    is_admin: bool,
+    wasm_version: String,
+    wasm_module: Vec<u8>,
+    settings: CollectionSettings,
+    tokens: Vec<InitialToken>,
+}
+
+async fn run_launch(
+    job: LaunchJob,
+    mut arg: InitArg,
+    payment: Option<LaunchPayment>,
+) -> Result<Principal, LaunchError> {
+    let LaunchJob {
+        id: launch_id,
+        creator: caller,
+        is_admin,
+        wasm_version,
+        wasm_module,
+        settings,
+        tokens,
+    } = job;
+    let price = STATE.with(|s| s.borrow().launch_price(settings.with_asset_canister()));
+    let fee = if is_admin {
+        None
+    } else {
+        let payment = payment.unwrap_or(LaunchPayment::Cycles);
+        let fee = collect_launch_fee(caller, payment, &price)
+            .await
+            .map_err(LaunchError::PaymentFailed)?;
+        STATE.with(|s| s.borrow_mut().set_launch_fee(launch_id, fee.clone()));
+        Some(fee)
+    };
+
+    STATE.with(|s| {
+        s.borrow_mut()
+            .set_launch_status(launch_id, LaunchStatus::Creating, ic_cdk::api::time())
+    });
+    let canister_settings = settings.canister_settings(ic_cdk::id(), caller);
+    let asset_canister = if settings.with_asset_canister() {
+        let result =
+            match acquire_canister(canister_settings.clone(), price.asset_canister_cycles).await {
+                Ok(asset_canister) => {
+                    STATE.with(|s| {
+                        s.borrow_mut()
+                            .set_launch_asset_canister(launch_id, asset_canister)
+                    });
+                    install_assets(asset_canister)
+                        .await
+                        .map(|()| asset_canister)
+                }
+                Err(err) => Err(err),
+            };
+        match result {
+            Ok(asset_canister) => Some(asset_canister),
+            Err(err) => return Err(abort_launch(caller, fee, None, err).await),
+        }
+    } else {
+        None
+    };
+    let canister_id = match acquire_canister(
+        canister_settings,
+        price.cycles - price.asset_canister_cycles,
     )
     .await
     {
-        Err((code, msg)) => return Err(format!("Rejection Code: {:?}, Message: {:?}", code, msg)),
-        Ok((principal,)) => principal.canister_id,
+        Ok(canister_id) => canister_id,
+        Err(err) => return Err(abort_launch(caller, fee, asset_canister, err).await),
+    };
+
+    if let Some(asset_canister) = asset_canister {
+        let config = STATE.with(|s| s.borrow().launch_config());
+        arg.icrc7_asset_base_url
+            .get_or_insert_with(|| config.asset_base_url(&asset_canister));
+    }
+    let minting_account = arg.minting_account.unwrap_or(Account {
+        owner: caller,
+        subaccount: None,
+    });
+    // the launchpad mints the initial tokens before handing the minting account over
+    arg.minting_account = if tokens.is_empty() {
+        Some(minting_account)
+    } else {
+        Some(launchpad_minting_account())
     };
-    let init_arg = InitArg::from((account, arg));
-    let init_arg = Encode!(&init_arg).unwrap();
-    match install_code(InstallCodeArgument {
-        mode: ic_cdk::api::management_canister::main::CanisterInstallMode::Install,
-        canister_id: principal,
-        wasm_module: ICRC7_WASM.to_vec(),
-        arg: init_arg,
+    // archives the launchpad keeps controlling are monitored along with the collection
+    if settings.keeps_launchpad_controller() {
+        // the settings the ledger defaults to
+        let controllers = arg
+            .archive_init
+            .get_or_insert_with(|| archive_init(2_000, 1_000))
+            .archive_controllers
+            .get_or_insert(None)
+            .get_or_insert_with(Vec::new);
+        if !controllers.contains(&ic_cdk::id()) {
+            controllers.push(ic_cdk::id());
+        }
+    }
+    let symbol = arg.icrc7_symbol.clone();
+    let name = arg.icrc7_name.clone();
+    let init_arg = Encode!(&LedgerArg::Init(arg)).unwrap();
+    if let Err(err) = install_collection(launch_id, canister_id, wasm_module, init_arg).await {
+        return Err(abort_launch(caller, fee, asset_canister, err).await);
+    }
+    if let Some(fee) = &fee {
+        accept_launch_fee(fee);
+    }
+    // the launch is over before the next call, an upgrade can't undo it anymore
+    let has_tokens = !tokens.is_empty();
+    let mut info = CollectionInfo {
+        canister_id,
+        creator: caller,
+        symbol,
+        name,
+        created_at: ic_cdk::api::time(),
+        wasm_version,
+        asset_canister,
+        launchpad_controller: Some(true),
+        pending_handover: None,
+        handover: None,
+    };
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.set_launch_status(
+            launch_id,
+            LaunchStatus::Installed { canister_id },
+            ic_cdk::api::time(),
+        );
+        s.register_collection(info.clone());
+        if has_tokens {
+            s.start_mint_job(canister_id, minting_account, tokens, ic_cdk::api::time());
+        }
+    });
+    schedule_collection_refresh(canister_id);
+    if has_tokens {
+        schedule_mint_batch(canister_id);
+    }
+    if !settings.keeps_launchpad_controller() {
+        if leave_canister(canister_id, &settings, caller).await {
+            info.launchpad_controller = Some(false);
+            STATE.with(|s| s.borrow_mut().register_collection(info));
+        }
+        if let Some(asset_canister) = asset_canister {
+            leave_canister(asset_canister, &settings, caller).await;
+        }
+    }
+    Ok(canister_id)
+}
+
+// Resumes the paused mint job of a collection at its cursor, allowed to the creator of the
+// collection and to the controllers of the launchpad.
+#[update]
+fn resume_minting(canister_id: Principal) -> Result<MintJob, String> {
+    let caller = ic_cdk::caller();
+    let info = STATE
+        .with(|s| s.borrow().get_collection(&canister_id))
+        .ok_or_else(|| format!("Unknown collection {}", canister_id))?;
+    if info.creator != caller && !ic_cdk::api::is_controller(&caller) {
+        return Err("The caller is not the creator of the collection".into());
+    }
+    let job = STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let mut job = s.mint_job(&canister_id).ok_or("No mint job")?;
+        if job.running || job.finished_at.is_some() {
+            return Err(String::from("The mint job is not paused"));
+        }
+        job.running = true;
+        job.last_error = None;
+        s.set_mint_job(job.clone());
+        Ok(job)
+    })?;
+    schedule_mint_batch(canister_id);
+    Ok(job)
+}
+
+// Refunds the fee of a launch an upgrade interrupted and reclaims its canisters.
+pub async fn abort_interrupted_launch(launch: Launch) {
+    if let LaunchStatus::Installing { canister_id, .. } = launch.status {
+        reclaim_canister(canister_id).await;
+    }
+    abort_launch(
+        launch.creator,
+        launch.fee,
+        launch.asset_canister,
+        LaunchError::Interrupted,
+    )
+    .await;
+}
+
+// Refunds the fee of a failed launch. The canisters already set up are reclaimed with
+// their cycles for the next launches.
+async fn abort_launch(
+    caller: Principal,
+    fee: Option<LaunchFee>,
+    asset_canister: Option<Principal>,
+    err: LaunchError,
+) -> LaunchError {
+    if let Some(asset_canister) = asset_canister {
+        reclaim_canister(asset_canister).await;
+    }
+    if let Some(fee) = fee {
+        refund_launch_fee(caller, fee).await;
+    }
+    err
+}
+
+// Hands the control of a collection over to the controllers chosen by its creator.
+#[update]
+async fn transfer_collection_ownership(
+    canister_id: Principal,
+    arg: HandoverArg,
+) -> Result<CollectionInfo, String> {
+    let caller = ic_cdk::caller();
+    let info = STATE
+        .with(|s| s.borrow().get_collection(&canister_id))
+        .ok_or_else(|| format!("Unknown collection {}", canister_id))?;
+    if info.creator != caller {
+        return Err("The caller is not the creator of the collection".into());
+    }
+    if info.is_handed_over() {
+        return Err("The launchpad is not a controller of the collection anymore".into());
+    }
+    // the launchpad holds the minting authority until the initial tokens are minted
+    if STATE
+        .with(|s| s.borrow().mint_job(&canister_id))
+        .is_some_and(|job| job.finished_at.is_none())
+    {
+        return Err("The initial tokens of the collection are not all minted".into());
+    }
+    hand_over_collection(info, arg, caller).await
+}
+
+#[update(guard = "admin_guard")]
+fn set_template(template: LaunchTemplate) -> Result<(), String> {
+    STATE.with(|s| s.borrow_mut().set_template(template))
+}
+
+#[update(guard = "admin_guard")]
+fn remove_template(name: String) -> Option<LaunchTemplate> {
+    STATE.with(|s| s.borrow_mut().remove_template(&name))
+}
+
+#[update(guard = "admin_guard")]
+fn set_launch_config(config: LaunchConfig) -> Result<(), String> {
+    STATE.with(|s| s.borrow_mut().set_launch_config(config))
+}
+
+#[update(guard = "admin_guard")]
+fn set_monitor_config(config: MonitorConfig) -> Result<(), String> {
+    STATE.with(|s| s.borrow_mut().set_monitor_config(config))?;
+    schedule_monitoring();
+    Ok(())
+}
+
+// Sets the top-up policy of a collection, None reverts it to the default one.
+#[update(guard = "admin_guard")]
+fn set_top_up_policy(collection: Principal, policy: Option<TopUpPolicy>) -> Result<(), String> {
+    if STATE
+        .with(|s| s.borrow().get_collection(&collection))
+        .is_none()
+    {
+        return Err(format!("Unknown collection {}", collection));
+    }
+    STATE.with(|s| s.borrow_mut().set_top_up_policy(collection, policy));
+    Ok(())
+}
+
+#[update(guard = "admin_guard")]
+async fn run_monitoring_now() {
+    run_monitoring().await
+}
+
+#[update(guard = "admin_guard")]
+async fn refresh_collection_metadata_now() {
+    refresh_all_metadata().await
+}
+
+#[update(guard = "admin_guard")]
+fn start_wasm_upload(version: String, sha256: Vec<u8>) -> Result<(), String> {
+    STATE.with(|s| s.borrow_mut().start_wasm_upload(version, sha256))
+}
+
+#[update(guard = "admin_guard")]
+fn upload_wasm_chunk(version: String, chunk: Vec<u8>) -> Result<u64, String> {
+    STATE.with(|s| s.borrow_mut().upload_wasm_chunk(version, chunk))
+}
+
+#[update(guard = "admin_guard")]
+fn commit_wasm_upload(version: String) -> Result<WasmInfo, String> {
+    STATE.with(|s| {
+        s.borrow_mut()
+            .commit_wasm_upload(version, ic_cdk::caller(), ic_cdk::api::time())
     })
+}
+
+// Upgrades a collection, allowed to its creator and to the controllers of the launchpad.
+#[update]
+async fn upgrade_collection(
+    canister_id: Principal,
+    version: Option<String>,
+    upgrade_arg: Option<UpgradeArg>,
+) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let info = STATE
+        .with(|s| s.borrow().get_collection(&canister_id))
+        .ok_or_else(|| format!("Unknown collection {}", canister_id))?;
+    if info.creator != caller && !ic_cdk::api::is_controller(&caller) {
+        return Err("The caller is not the creator of the collection".into());
+    }
+    let (version, wasm_module) = STATE.with(|s| s.borrow().wasm_module(version))?;
+    upgrade_collection_canister(
+        canister_id,
+        version,
+        wasm_module,
+        encode_upgrade_arg(upgrade_arg),
+    )
     .await
+}
+
+// Starts upgrading every registered collection to `version`, in batches run by a timer.
+#[update(guard = "admin_guard")]
+fn start_rollout(
+    version: Option<String>,
+    upgrade_arg: Option<UpgradeArg>,
+    batch_size: Option<u32>,
+) -> Result<Rollout, String> {
+    if STATE
+        .with(|s| s.borrow().rollout())
+        .is_some_and(|rollout| rollout.running)
     {
-        Ok(()) => Ok(principal),
-        Err((code, msg)) => Err(format!("Code: {:?}, Message: {:?}", code, msg)),
+        return Err("A rollout is already running".into());
     }
+    let (version, _) = STATE.with(|s| s.borrow().wasm_module(version))?;
+    let rollout = Rollout {
+        version,
+        arg: encode_upgrade_arg(upgrade_arg),
+        batch_size: batch_size
+            .unwrap_or(DEFAULT_ROLLOUT_BATCH_SIZE)
+            .clamp(1, MAX_ROLLOUT_BATCH_SIZE),
+        cursor: None,
+        running: true,
+        upgraded: 0,
+        skipped: 0,
+        failed: 0,
+        started_at: ic_cdk::api::time(),
+        finished_at: None,
+    };
+    STATE.with(|s| s.borrow_mut().set_rollout(Some(rollout.clone())));
+    schedule_rollout_batch();
+    Ok(rollout)
+}
+
+// Stops the rollout after the collection being upgraded.
+#[update(guard = "admin_guard")]
+fn pause_rollout() -> Result<Rollout, String> {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let mut rollout = s.rollout().ok_or("No rollout")?;
+        rollout.running = false;
+        s.set_rollout(Some(rollout.clone()));
+        Ok(rollout)
+    })
+}
+
+// Continues a paused rollout after its cursor.
+#[update(guard = "admin_guard")]
+fn resume_rollout() -> Result<Rollout, String> {
+    let rollout = STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let mut rollout = s.rollout().ok_or("No rollout")?;
+        if rollout.running || rollout.finished_at.is_some() {
+            return Err(String::from("The rollout is not paused"));
+        }
+        rollout.running = true;
+        s.set_rollout(Some(rollout.clone()));
+        Ok(rollout)
+    })?;
+    schedule_rollout_batch();
+    Ok(rollout)
 }
@@ -0,0 +1,27 @@
+use ic_cdk::api::management_canister::main::{deposit_cycles, CanisterIdRecord};
+use ic_cdk_macros::{export_candid, update};
+
+// Cycles kept to pay for the deposit call itself, the rest goes back to the caller.
+const RESERVED_CYCLES: u128 = 1_000_000_000;
+
+// Installed by the launchpad into a canister it is about to delete, so that the cycles
+// of the canister go back to the launchpad instead of being burnt with it.
+#[update]
+async fn refund() -> Result<u128, String> {
+    let caller = ic_cdk::caller();
+    if !ic_cdk::api::is_controller(&caller) {
+        return Err("The caller is not a controller".into());
+    }
+    let amount = ic_cdk::api::canister_balance128().saturating_sub(RESERVED_CYCLES);
+    deposit_cycles(
+        CanisterIdRecord {
+            canister_id: caller,
+        },
+        amount,
+    )
+    .await
+    .map_err(|(code, msg)| format!("Code: {:?}, Message: {:?}", code, msg))?;
+    Ok(amount)
+}
+
+export_candid!();
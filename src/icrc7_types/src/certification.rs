@@ -0,0 +1,305 @@
+use candid::Principal;
+use ic_certification::{Certificate, HashTree, LookupResult};
+use ic_verify_bls_signature::verify_bls_signature;
+use icrc_ledger_types::icrc3::blocks::DataCertificate;
+use serde_bytes::ByteBuf;
+
+// DER prefix of the BLS12-381 public keys used by the IC, followed by the 96 bytes raw key.
+const IC_ROOT_KEY_DER_PREFIX: &[u8; 37] =
+    b"\x30\x81\x82\x30\x1d\x06\x0d\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x01\x02\x01\x06\x0c\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x02\x01\x03\x61\x00";
+const IC_ROOT_KEY_LENGTH: usize = 96;
+const IC_STATE_ROOT_DOMAIN_SEPARATOR: &[u8; 14] = b"\x0Dic-state-root";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedTip {
+    pub last_block_index: u128,
+    pub last_block_hash: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateError {
+    MissingCertificate,
+    MalformedCertificate(String),
+    MalformedHashTree(String),
+    MalformedRootKey,
+    InvalidSignature,
+    InvalidDelegation(String),
+    CertifiedDataMismatch,
+    MissingLabel(String),
+    MalformedLabel(String),
+    // the certificate `time` is further than the allowed age from now
+    StaleCertificate { time: u64 },
+}
+
+// Verifies the answer of `icrc3_get_tip_certificate` of the ledger `canister_id`.
+//
+// `root_key` is the DER encoded IC root key, the certificate signature and its optional
+// subnet delegation are checked against it before the tip is read from the witness.
+// A replayed old certificate is still validly signed, so the certificate `time` must be
+// within `max_age_nanos` of `now_nanos` (both in nanoseconds since the epoch).
+pub fn verify_tip_certificate(
+    data_certificate: &DataCertificate,
+    canister_id: &Principal,
+    root_key: &[u8],
+    now_nanos: u64,
+    max_age_nanos: u64,
+) -> Result<VerifiedTip, CertificateError> {
+    let certificate = data_certificate
+        .certificate
+        .as_ref()
+        .ok_or(CertificateError::MissingCertificate)?;
+    let certificate = decode_certificate(certificate)?;
+    verify_certificate(&certificate, canister_id, root_key)?;
+
+    let time = leb128_decode(lookup(&certificate.tree, &[b"time"])?)
+        .and_then(|time| u64::try_from(time).ok())
+        .ok_or_else(|| CertificateError::MalformedLabel("time".into()))?;
+    if time.abs_diff(now_nanos) > max_age_nanos {
+        return Err(CertificateError::StaleCertificate { time });
+    }
+
+    let certified_data = lookup(
+        &certificate.tree,
+        &[b"canister", canister_id.as_slice(), b"certified_data"],
+    )?;
+
+    let witness: HashTree = serde_cbor::from_slice(&data_certificate.hash_tree)
+        .map_err(|e| CertificateError::MalformedHashTree(e.to_string()))?;
+    if witness.digest().as_slice() != certified_data {
+        return Err(CertificateError::CertifiedDataMismatch);
+    }
+
+    let last_block_index = lookup(&witness, &[b"last_block_index"])?;
+    let last_block_index = leb128_decode(last_block_index)
+        .ok_or_else(|| CertificateError::MalformedLabel("last_block_index".into()))?;
+    let last_block_hash = lookup(&witness, &[b"last_block_hash"])?;
+    let last_block_hash = last_block_hash
+        .try_into()
+        .map_err(|_| CertificateError::MalformedLabel("last_block_hash".into()))?;
+
+    Ok(VerifiedTip {
+        last_block_index,
+        last_block_hash,
+    })
+}
+
+fn decode_certificate(bytes: &[u8]) -> Result<Certificate, CertificateError> {
+    serde_cbor::from_slice(bytes).map_err(|e| CertificateError::MalformedCertificate(e.to_string()))
+}
+
+fn verify_certificate(
+    certificate: &Certificate,
+    canister_id: &Principal,
+    root_key: &[u8],
+) -> Result<(), CertificateError> {
+    let key = match &certificate.delegation {
+        None => root_key.to_vec(),
+        Some(delegation) => {
+            let delegation_certificate = decode_certificate(&delegation.certificate)?;
+            if delegation_certificate.delegation.is_some() {
+                return Err(CertificateError::InvalidDelegation(
+                    "nested delegations are not allowed".into(),
+                ));
+            }
+            verify_certificate(&delegation_certificate, canister_id, root_key)?;
+            check_canister_ranges(
+                &delegation_certificate.tree,
+                &delegation.subnet_id,
+                canister_id,
+            )?;
+            lookup(
+                &delegation_certificate.tree,
+                &[b"subnet", delegation.subnet_id.as_slice(), b"public_key"],
+            )?
+            .to_vec()
+        }
+    };
+    let key = extract_der_key(&key)?;
+
+    let mut message = IC_STATE_ROOT_DOMAIN_SEPARATOR.to_vec();
+    message.extend_from_slice(&certificate.tree.digest());
+    verify_bls_signature(&certificate.signature, &message, key)
+        .map_err(|_| CertificateError::InvalidSignature)
+}
+
+fn check_canister_ranges(
+    tree: &HashTree,
+    subnet_id: &[u8],
+    canister_id: &Principal,
+) -> Result<(), CertificateError> {
+    let ranges = lookup(tree, &[b"subnet", subnet_id, b"canister_ranges"])?;
+    let ranges: Vec<(ByteBuf, ByteBuf)> = serde_cbor::from_slice(ranges)
+        .map_err(|e| CertificateError::InvalidDelegation(e.to_string()))?;
+    let canister_id = canister_id.as_slice();
+    if ranges
+        .iter()
+        .any(|(start, end)| start.as_slice() <= canister_id && canister_id <= end.as_slice())
+    {
+        Ok(())
+    } else {
+        Err(CertificateError::InvalidDelegation(
+            "canister is not in the delegated subnet ranges".into(),
+        ))
+    }
+}
+
+fn extract_der_key(der_key: &[u8]) -> Result<&[u8], CertificateError> {
+    if der_key.len() != IC_ROOT_KEY_DER_PREFIX.len() + IC_ROOT_KEY_LENGTH
+        || !der_key.starts_with(IC_ROOT_KEY_DER_PREFIX)
+    {
+        return Err(CertificateError::MalformedRootKey);
+    }
+    Ok(&der_key[IC_ROOT_KEY_DER_PREFIX.len()..])
+}
+
+fn lookup<'a>(tree: &'a HashTree, path: &[&[u8]]) -> Result<&'a [u8], CertificateError> {
+    match tree.lookup_path(path) {
+        LookupResult::Found(value) => Ok(value),
+        _ => Err(CertificateError::MissingLabel(
+            path.iter()
+                .map(|label| String::from_utf8_lossy(label).into_owned())
+                .collect::<Vec<_>>()
+                .join("/"),
+        )),
+    }
+}
+
+pub fn leb128_encode(mut value: u128) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+pub fn leb128_decode(bytes: &[u8]) -> Option<u128> {
+    let mut value: u128 = 0;
+    for (index, byte) in bytes.iter().enumerate() {
+        let shift = 7 * index as u32;
+        let bits = (byte & 0x7f) as u128;
+        // the last group only has room for the two high bits of a u128
+        if shift >= 128 || (bits << shift) >> shift != bits {
+            return None;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return (index == bytes.len() - 1).then_some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_certification::hash_tree::{fork, label, leaf};
+    use ic_verify_bls_signature::PrivateKey;
+
+    fn root_key(private_key: &PrivateKey) -> Vec<u8> {
+        let mut der = IC_ROOT_KEY_DER_PREFIX.to_vec();
+        der.extend_from_slice(&private_key.public_key().serialize());
+        der
+    }
+
+    fn tip_witness() -> HashTree {
+        fork(
+            label("last_block_hash", leaf([7u8; 32].to_vec())),
+            label("last_block_index", leaf(leb128_encode(624485))),
+        )
+    }
+
+    const NOW: u64 = 1_700_000_000_000_000_000;
+    const MAX_AGE: u64 = 300_000_000_000;
+
+    fn data_certificate(
+        private_key: &PrivateKey,
+        canister_id: &Principal,
+        certified_data: [u8; 32],
+        time: u64,
+    ) -> DataCertificate {
+        let tree = fork(
+            label(
+                "canister",
+                label(
+                    canister_id.as_slice().to_vec(),
+                    label("certified_data", leaf(certified_data.to_vec())),
+                ),
+            ),
+            label("time", leaf(leb128_encode(time as u128))),
+        );
+        let mut message = IC_STATE_ROOT_DOMAIN_SEPARATOR.to_vec();
+        message.extend_from_slice(&tree.digest());
+        let certificate = Certificate {
+            tree,
+            signature: private_key.sign(&message).serialize().to_vec(),
+            delegation: None,
+        };
+        DataCertificate {
+            certificate: Some(ByteBuf::from(serde_cbor::to_vec(&certificate).unwrap())),
+            hash_tree: ByteBuf::from(serde_cbor::to_vec(&tip_witness()).unwrap()),
+        }
+    }
+
+    #[test]
+    fn verifies_tip_certificate() {
+        let private_key = PrivateKey::deserialize(&[1u8; 32]).unwrap();
+        let canister_id = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+        let certificate = data_certificate(&private_key, &canister_id, tip_witness().digest(), NOW);
+        let verify = |certificate: &DataCertificate, root_key: &[u8], now: u64| {
+            verify_tip_certificate(certificate, &canister_id, root_key, now, MAX_AGE)
+        };
+
+        assert_eq!(
+            verify(&certificate, &root_key(&private_key), NOW + MAX_AGE),
+            Ok(VerifiedTip {
+                last_block_index: 624485,
+                last_block_hash: [7u8; 32],
+            })
+        );
+
+        let other_key = PrivateKey::deserialize(&[2u8; 32]).unwrap();
+        assert_eq!(
+            verify(&certificate, &root_key(&other_key), NOW),
+            Err(CertificateError::InvalidSignature)
+        );
+        assert_eq!(
+            verify(&certificate, &root_key(&private_key), NOW + MAX_AGE + 1),
+            Err(CertificateError::StaleCertificate { time: NOW })
+        );
+
+        let certificate = data_certificate(&private_key, &canister_id, [0u8; 32], NOW);
+        assert_eq!(
+            verify(&certificate, &root_key(&private_key), NOW),
+            Err(CertificateError::CertifiedDataMismatch)
+        );
+    }
+
+    #[test]
+    fn leb128_encode_test_vectors() {
+        assert_eq!(leb128_encode(0), vec![0x00]);
+        assert_eq!(leb128_encode(127), vec![0x7f]);
+        assert_eq!(leb128_encode(128), vec![0x80, 0x01]);
+        assert_eq!(leb128_encode(624485), vec![0xe5, 0x8e, 0x26]);
+        assert_eq!(leb128_decode(&leb128_encode(u128::MAX)), Some(u128::MAX));
+    }
+
+    #[test]
+    fn leb128_decode_test_vectors() {
+        assert_eq!(leb128_decode(&[0x00]), Some(0));
+        assert_eq!(leb128_decode(&[0x80, 0x01]), Some(128));
+        assert_eq!(leb128_decode(&[0xe5, 0x8e, 0x26]), Some(624485));
+        assert_eq!(leb128_decode(&[0x80]), None);
+        assert_eq!(leb128_decode(&[0x00, 0x01]), None);
+
+        let mut max = vec![0xff; 18];
+        max.push(0x03);
+        assert_eq!(leb128_decode(&max), Some(u128::MAX));
+        max[18] = 0x04;
+        assert_eq!(leb128_decode(&max), None);
+    }
+}
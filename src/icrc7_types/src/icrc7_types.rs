@@ -16,6 +16,8 @@ use crate::{
 
 pub static TRANSACTION_TRANSFER_OP: &str = "7xfer";
 pub static TRANSACTION_TRANSFER_FROM_OP: &str = "37xfer";
+pub static TRANSACTION_ADMIN_BURN_OP: &str = "7admin_burn";
+pub static TRANSACTION_ADMIN_TRANSFER_OP: &str = "7admin_xfer";
 
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
 pub enum TransactionType {
@@ -28,7 +30,7 @@ pub enum TransactionType {
     Burn {
         tid: u128,
         from: Account,
-        to: Account,
+        to: Option<Account>,
     },
     Transfer {
         tid: u128,
@@ -61,6 +63,18 @@ pub enum TransactionType {
         from: Account,
         to: Option<Account>,
     },
+    AdminBurn {
+        tid: u128,
+        from: Account,
+        to: Option<Account>,
+        authority: Account,
+    },
+    AdminTransfer {
+        tid: u128,
+        from: Account,
+        to: Account,
+        authority: Account,
+    },
 }
 
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone, Default)]
@@ -75,6 +89,8 @@ pub struct Transaction {
     pub meta: Option<Icrc7TokenMetadata>,
     pub memo: Option<Vec<u8>>,
     pub block: Option<Block>,
+    pub authority: Option<Account>,
+    pub created_at_time: Option<u64>,
 }
 
 impl Transaction {
@@ -244,6 +260,46 @@ impl Transaction {
         }
     }
 
+    pub fn admin_burn(
+        now_sec: u64,
+        tid: u128,
+        from: Account,
+        to: Option<Account>,
+        authority: Account,
+        memo: Option<Vec<u8>>,
+    ) -> Self {
+        Transaction {
+            ts: now_sec,
+            op: TRANSACTION_ADMIN_BURN_OP.to_string(),
+            tid,
+            from: Some(from),
+            to,
+            authority: Some(authority),
+            memo,
+            ..Default::default()
+        }
+    }
+
+    pub fn admin_transfer(
+        now_sec: u64,
+        tid: u128,
+        from: Account,
+        to: Account,
+        authority: Account,
+        memo: Option<Vec<u8>>,
+    ) -> Self {
+        Transaction {
+            ts: now_sec,
+            op: TRANSACTION_ADMIN_TRANSFER_OP.to_string(),
+            tid,
+            from: Some(from),
+            to: Some(to),
+            authority: Some(authority),
+            memo,
+            ..Default::default()
+        }
+    }
+
     pub fn new(_txn_id: u128, txn_type: TransactionType, at: u64, memo: Option<Vec<u8>>) -> Self {
         let transaction = match &txn_type {
             TransactionType::Transfer { tid, from, to } => {
@@ -263,7 +319,7 @@ impl Transaction {
                 memo,
             ),
             TransactionType::Burn { tid, from, to } => {
-                Self::burn(at, tid.clone(), from.clone(), Some(to.clone()), memo)
+                Self::burn(at, tid.clone(), from.clone(), *to, memo)
             }
             TransactionType::Approval {
                 tid,
@@ -281,11 +337,11 @@ impl Transaction {
             TransactionType::ApproveCollection { from, to, exp_sec } => {
                 Self::approve_collection(at, from.clone(), to.clone(), exp_sec.clone(), memo)
             }
-            TransactionType::Revoke { tid, from, to: _ } => {
-                Self::revoke(at, tid.clone(), from.clone(), None, memo)
+            TransactionType::Revoke { tid, from, to } => {
+                Self::revoke(at, tid.clone(), from.clone(), *to, memo)
             }
-            TransactionType::RevokeCollection { from, to: _ } => {
-                Self::revoke_collection(at, from.clone(), None, memo)
+            TransactionType::RevokeCollection { from, to } => {
+                Self::revoke_collection(at, from.clone(), *to, memo)
             }
             TransactionType::TransferFrom {
                 tid,
@@ -300,6 +356,18 @@ impl Transaction {
                 spender.clone(),
                 memo,
             ),
+            TransactionType::AdminBurn {
+                tid,
+                from,
+                to,
+                authority,
+            } => Self::admin_burn(at, *tid, *from, *to, *authority, memo),
+            TransactionType::AdminTransfer {
+                tid,
+                from,
+                to,
+                authority,
+            } => Self::admin_transfer(at, *tid, *from, *to, *authority, memo),
         };
         return transaction;
     }
@@ -354,6 +422,34 @@ pub struct BurnArg {
 
 pub type BurnResult = Result<u128, BurnError>;
 
+// Burns any token, only available to the minting authority when admin actions are enabled
+#[derive(CandidType, Deserialize, Clone)]
+pub struct AdminBurnArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub token_id: u128,
+    // the reason of the action, e.g. a legal takedown reference
+    pub memo: Vec<u8>,
+}
+
+// Moves any token to a new owner, only available to the minting authority when admin actions are enabled
+#[derive(CandidType, Deserialize, Clone)]
+pub struct AdminTransferArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub token_id: u128,
+    // the reason of the action, e.g. a key recovery request id
+    pub memo: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum BurnMode {
+    // the token is removed from the ledger, its id stays reserved
+    Tombstone,
+    // the token is moved to the burn account
+    #[default]
+    BurnAddress,
+}
+
 #[derive(CandidType, Deserialize)]
 pub struct InitArg {
     pub minting_account: Option<Account>,
@@ -361,6 +457,8 @@ pub struct InitArg {
     pub icrc7_name: String,
     pub icrc7_description: Option<String>,
     pub icrc7_logo: Option<String>,
+    // where the images and files of the collection are served from
+    pub icrc7_asset_base_url: Option<String>,
     pub icrc7_supply_cap: Option<u128>,
     pub icrc7_max_query_batch_size: Option<u16>,
     pub icrc7_max_update_batch_size: Option<u16>,
@@ -372,9 +470,203 @@ pub struct InitArg {
     pub permitted_drift: Option<u64>,
     pub approval_init: Option<InitApprovalsArg>,
     pub archive_init: Option<InitArchiveArg>,
+    pub burn_mode: Option<BurnMode>,
+    pub allow_admin_actions: Option<bool>,
+}
+
+pub const MAX_SYMBOL_LENGTH: usize = 32;
+pub const MAX_NAME_LENGTH: usize = 128;
+// below this the ledger never has enough cycles to spawn an archive
+pub const MIN_ARCHIVE_CYCLES: u128 = 1_000_000_000_000;
+
+// A field of an `InitArg` that can't be used, named as in candid
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &str, message: &str) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl InitArg {
+    // Every field that can't be used, checked before the ledger is installed.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = vec![];
+        let mut check = |ok: bool, field: &str, message: &str| {
+            if !ok {
+                errors.push(FieldError::new(field, message));
+            }
+        };
+
+        check(
+            !self.icrc7_symbol.trim().is_empty(),
+            "icrc7_symbol",
+            "must not be empty",
+        );
+        check(
+            self.icrc7_symbol.chars().count() <= MAX_SYMBOL_LENGTH,
+            "icrc7_symbol",
+            &format!("must be at most {} characters", MAX_SYMBOL_LENGTH),
+        );
+        check(
+            !self.icrc7_name.trim().is_empty(),
+            "icrc7_name",
+            "must not be empty",
+        );
+        check(
+            self.icrc7_name.chars().count() <= MAX_NAME_LENGTH,
+            "icrc7_name",
+            &format!("must be at most {} characters", MAX_NAME_LENGTH),
+        );
+        check(
+            self.icrc7_supply_cap != Some(0),
+            "icrc7_supply_cap",
+            "must be positive",
+        );
+        check(
+            self.icrc7_max_query_batch_size != Some(0),
+            "icrc7_max_query_batch_size",
+            "must be positive",
+        );
+        check(
+            self.icrc7_max_update_batch_size != Some(0),
+            "icrc7_max_update_batch_size",
+            "must be positive",
+        );
+        check(
+            self.icrc7_max_take_value != Some(0),
+            "icrc7_max_take_value",
+            "must be positive",
+        );
+        check(
+            self.icrc7_default_take_value != Some(0),
+            "icrc7_default_take_value",
+            "must be positive",
+        );
+        if let (Some(default_take), Some(max_take)) =
+            (self.icrc7_default_take_value, self.icrc7_max_take_value)
+        {
+            check(
+                default_take <= max_take,
+                "icrc7_default_take_value",
+                "must not exceed icrc7_max_take_value",
+            );
+        }
+        check(
+            self.icrc7_max_memo_size != Some(0),
+            "icrc7_max_memo_size",
+            "must be positive",
+        );
+        check(self.tx_window != Some(0), "tx_window", "must be positive");
+
+        if let Some(approval_init) = &self.approval_init {
+            check(
+                approval_init.max_approvals != Some(0),
+                "approval_init.max_approvals",
+                "must be positive",
+            );
+            check(
+                approval_init.max_approvals_per_token_or_collection != Some(0),
+                "approval_init.max_approvals_per_token_or_collection",
+                "must be positive",
+            );
+            if let (Some(settle_to), Some(max_approvals)) = (
+                approval_init.settle_to_approvals,
+                approval_init.max_approvals,
+            ) {
+                check(
+                    settle_to <= max_approvals,
+                    "approval_init.settle_to_approvals",
+                    "must not exceed max_approvals",
+                );
+            }
+        }
+
+        if let Some(archive_init) = &self.archive_init {
+            check(
+                archive_init.archive_cycles >= MIN_ARCHIVE_CYCLES,
+                "archive_init.archiveCycles",
+                &format!("must be at least {}", MIN_ARCHIVE_CYCLES),
+            );
+            check(
+                archive_init.max_active_records > 0,
+                "archive_init.maxActiveRecords",
+                "must be positive",
+            );
+            check(
+                archive_init.settle_to_records <= archive_init.max_active_records,
+                "archive_init.settleToRecords",
+                "must not exceed maxActiveRecords",
+            );
+            check(
+                archive_init.max_records_to_archive > 0,
+                "archive_init.maxRecordsToArchive",
+                "must be positive",
+            );
+            check(
+                archive_init.max_records_to_archive <= archive_init.max_records_in_archive_instance,
+                "archive_init.maxRecordsToArchive",
+                "must not exceed maxRecordsInArchiveInstance",
+            );
+            check(
+                archive_init.max_archive_pages > 0,
+                "archive_init.maxArchivePages",
+                "must be positive",
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// Settings changed by `post_upgrade`, fields left unset keep their current value
+#[derive(CandidType, Deserialize, Default)]
+pub struct UpgradeArg {
+    pub minting_account: Option<Account>,
+    pub icrc7_symbol: Option<String>,
+    pub icrc7_name: Option<String>,
+    pub icrc7_description: Option<String>,
+    pub icrc7_logo: Option<String>,
+    // where the images and files of the collection are served from
+    pub icrc7_asset_base_url: Option<String>,
+    pub icrc7_supply_cap: Option<u128>,
+    pub icrc7_max_query_batch_size: Option<u16>,
+    pub icrc7_max_update_batch_size: Option<u16>,
+    pub icrc7_max_take_value: Option<u128>,
+    pub icrc7_default_take_value: Option<u128>,
+    pub icrc7_max_memo_size: Option<u32>,
+    pub icrc7_atomic_batch_transfers: Option<bool>,
+    pub tx_window: Option<u64>,
+    pub permitted_drift: Option<u64>,
+    pub approval_settings: Option<InitApprovalsArg>,
+    pub archive_settings: Option<InitArchiveArg>,
+}
+
+// The argument of the ledger canister, `Init` on install and `Upgrade` on upgrade
+#[derive(CandidType, Deserialize)]
+pub enum LedgerArg {
+    Init(InitArg),
+    Upgrade(Option<UpgradeArg>),
 }
 
-#[derive(CandidType)]
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Standard {
     pub name: String,
     pub url: String,
@@ -1,4 +1,121 @@
-pub mod icrc3_types;
+pub mod certification;
+pub mod errors;
 pub mod icrc37_types;
+pub mod icrc3_types;
 pub mod icrc7_types;
-pub mod errors;
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        icrc3_types::{IndexType, InitArchiveArg},
+        icrc7_types::{InitArg, MAX_SYMBOL_LENGTH, MIN_ARCHIVE_CYCLES},
+    };
+
+    // These modules are generated from the ledger by `scripts/sync_types.sh`, so that the
+    // launchpad checks an `InitArg` with the very validator the ledger runs in `init`.
+    #[test]
+    fn generated_modules_match_the_ledger() {
+        assert!(include_str!("icrc7_types.rs") == include_str!("../../icrc7/src/icrc7_types.rs"));
+        assert!(include_str!("errors.rs") == include_str!("../../icrc7/src/errors.rs"));
+        assert!(
+            include_str!("icrc3_types.rs")
+                == include_str!("../../icrc7/src/icrc3_types.rs").replacen(
+                    "use crate::Transaction;",
+                    "use crate::icrc7_types::Transaction;",
+                    1
+                )
+        );
+    }
+
+    fn init_arg() -> InitArg {
+        InitArg {
+            minting_account: None,
+            icrc7_symbol: "ITEM".into(),
+            icrc7_name: "Items".into(),
+            icrc7_description: None,
+            icrc7_logo: None,
+            icrc7_asset_base_url: None,
+            icrc7_supply_cap: None,
+            icrc7_max_query_batch_size: None,
+            icrc7_max_update_batch_size: None,
+            icrc7_max_take_value: None,
+            icrc7_default_take_value: None,
+            icrc7_max_memo_size: None,
+            icrc7_atomic_batch_transfers: None,
+            tx_window: None,
+            permitted_drift: None,
+            approval_init: None,
+            archive_init: None,
+            burn_mode: None,
+            allow_admin_actions: None,
+        }
+    }
+
+    fn invalid_fields(arg: &InitArg) -> Vec<String> {
+        match arg.validate() {
+            Ok(()) => vec![],
+            Err(errors) => errors.into_iter().map(|error| error.field).collect(),
+        }
+    }
+
+    #[test]
+    fn init_args_are_validated() {
+        assert!(init_arg().validate().is_ok());
+
+        let mut arg = init_arg();
+        arg.icrc7_symbol = " ".into();
+        arg.icrc7_name = "N".repeat(200);
+        arg.icrc7_supply_cap = Some(0);
+        arg.icrc7_max_take_value = Some(10);
+        arg.icrc7_default_take_value = Some(20);
+        arg.tx_window = Some(0);
+        assert_eq!(
+            invalid_fields(&arg),
+            vec![
+                "icrc7_symbol",
+                "icrc7_name",
+                "icrc7_supply_cap",
+                "icrc7_default_take_value",
+                "tx_window"
+            ]
+        );
+
+        let mut arg = init_arg();
+        arg.icrc7_symbol = "S".repeat(MAX_SYMBOL_LENGTH + 1);
+        assert_eq!(invalid_fields(&arg), vec!["icrc7_symbol"]);
+    }
+
+    #[test]
+    fn archive_init_is_validated() {
+        let archive_init = InitArchiveArg {
+            archive_controllers: None,
+            archive_cycles: MIN_ARCHIVE_CYCLES,
+            archive_index_type: IndexType::Stable,
+            max_active_records: 100,
+            max_archive_pages: 10,
+            max_records_in_archive_instance: 1000,
+            max_records_to_archive: 50,
+            settle_to_records: 50,
+        };
+        let mut arg = init_arg();
+        arg.archive_init = Some(archive_init.clone());
+        assert!(arg.validate().is_ok());
+
+        arg.archive_init = Some(InitArchiveArg {
+            archive_cycles: MIN_ARCHIVE_CYCLES - 1,
+            settle_to_records: 101,
+            max_records_to_archive: 2000,
+            max_archive_pages: 0,
+            ..archive_init
+        });
+        assert_eq!(
+            invalid_fields(&arg),
+            vec![
+                "archive_init.archiveCycles",
+                "archive_init.settleToRecords",
+                "archive_init.maxRecordsToArchive",
+                "archive_init.maxArchivePages"
+            ]
+        );
+    }
+}